@@ -12,6 +12,7 @@ use serde::{Serialize, Deserialize};
 
 mod unixmoment;
 pub use unixmoment::*;
+use crate::{Schedule, WorkCalendar, WORKDAY_SEARCH, ResolveOptions, ParseError, resolve_with, SoundSelection};
 
 impl UnixMoment {
     pub fn timer_for(&self, now: UnixMoment) -> Option<Timer> {
@@ -26,6 +27,16 @@ pub enum AlarmState {
     Done,
 }
 
+/// What the audio task does when the sound of a ringing alarm ends
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SoundPolicy {
+    /// play the sound once
+    #[default]
+    Once,
+    /// play it again until the alarm is acknowledged
+    Loop,
+}
+
 #[derive(Debug, Clone)]
 pub enum AlarmEvent {
     Started(Alarm),
@@ -39,6 +50,24 @@ pub struct Alarm {
     pub end_t: UnixMoment,
     pub creation_t: UnixMoment,
     pub state: AlarmState,
+    pub recurrence: Option<Schedule>,
+    /// time expression that is resolved again after each ring, e.g. "sunset - 30m"
+    pub daily: Option<String>,
+    /// recurring alarm skips days off in the work calendar
+    pub workdays_only: bool,
+    /// set for imported alarms, see AlarmList::add
+    pub uid: Option<String>,
+    /// synced from the calendar directory, not meant to be edited by hand
+    pub managed: bool,
+    pub sound_policy: SoundPolicy,
+    /// how the file is picked when the sound has many
+    pub sound_selection: SoundSelection,
+    /// how many times the alarm has rung before, for SoundSelection::RoundRobin
+    pub sound_turn: usize,
+    pub notes: Option<String>,
+    pub tags: Vec<String>,
+    /// AppEvent::Warn was sent for it
+    pub warned: bool,
 }
 
 impl Alarm {
@@ -50,17 +79,64 @@ impl Alarm {
             end_t,
             creation_t,
             state: AlarmState::Waiting,
+            recurrence: None,
+            daily: None,
+            workdays_only: false,
+            uid: None,
+            managed: false,
+            sound_policy: SoundPolicy::Once,
+            sound_selection: SoundSelection::Random,
+            sound_turn: 0,
+            notes: None,
+            tags: Vec::new(),
+            warned: false,
         }
     }
     pub fn new_from_durations(name: impl Into<String>, soundfile: Option<PathBuf>, end_in: Duration) -> Self {
-        let creation_t = UnixMoment::now();
-        Self {
-            name: name.into(),
-            soundfile,
-            end_t: creation_t + end_in,
-            creation_t,
-            state: AlarmState::Waiting,
+        let mut a = Self::new_from_unixmoment(name, soundfile, UnixMoment::new(0));
+        a.end_t = a.creation_t + end_in;
+        a
+    }
+    /// For a recurring alarm, returns a fresh alarm for the next time its
+    /// schedule fires after this one.
+    pub fn next_occurrence(&self, now: UnixMoment, tz: tz::TimeZoneRef, options: ResolveOptions) -> Option<Self> {
+        let after = self.end_t.max(now);
+        let default_calendar = WorkCalendar::default();
+        let calendar = options.calendar.unwrap_or(&default_calendar);
+        let end_t = match (&self.recurrence, &self.daily) {
+            (Some(schedule), _) if self.workdays_only => schedule.next_working_after(after, tz, calendar)?,
+            (Some(schedule), _) => schedule.next_after(after, tz)?,
+            (None, Some(expr)) => Self::next_daily(expr, after, tz, options, self.workdays_only).ok()?,
+            (None, None) => return None,
+        };
+        let mut a = Self::new_from_unixmoment(self.name.clone(), self.soundfile.clone(), end_t);
+        a.recurrence = self.recurrence.clone();
+        a.daily = self.daily.clone();
+        a.workdays_only = self.workdays_only;
+        a.uid = self.uid.clone();
+        a.managed = self.managed;
+        a.sound_policy = self.sound_policy;
+        a.sound_selection = self.sound_selection;
+        a.sound_turn = self.sound_turn + 1;
+        a.notes = self.notes.clone();
+        a.tags = self.tags.clone();
+        Some(a)
+    }
+    /// Resolves a daily expression to its first time after `after`,
+    /// optionally skipping days off. Gives up when the expression keeps
+    /// landing on days off.
+    pub fn next_daily(expr: &str, after: UnixMoment, tz: tz::TimeZoneRef, options: ResolveOptions, workdays_only: bool) -> Result<UnixMoment, ParseError> {
+        let default_calendar = WorkCalendar::default();
+        let calendar = options.calendar.unwrap_or(&default_calendar);
+        let mut t = after;
+        for _ in 0..WORKDAY_SEARCH {
+            t = resolve_with(expr, t, tz, options)?.moment;
+            let Some(dt) = t.as_datetime(tz) else { return Ok(t) };
+            if ! workdays_only || calendar.is_workday((dt.year(), dt.month(), dt.month_day())) {
+                return Ok(t);
+            }
         }
+        Err(ParseError::NoWorkday)
     }
     pub fn is_running(&self) -> bool {
         ! matches!(self.state, AlarmState::Done)
@@ -83,21 +159,54 @@ impl Alarm {
     }
 }
 
+#[derive(Clone)]
 pub struct AlarmList(Vec<Alarm>, Vec<Alarm>);
 impl AlarmList {
     pub fn new() -> Self {
         Self(Vec::new(), Vec::new())
     }
+    /// Adds the alarm, replacing any pending alarm with the same uid
     pub fn add(&mut self, a: Alarm) {
+        if let Some(ref uid) = a.uid {
+            self.0.retain(|x| x.uid.as_ref() != Some(uid));
+        }
         self.0.push(a);
-        self.0.sort_by(|a, b| b.end_t.cmp(&a.end_t));
+        // latest first, alarms at the same time in the order they were made
+        self.0.sort_by(|a, b| (b.end_t, b.creation_t).cmp(&(a.end_t, a.creation_t)));
     }
     pub fn remove(&mut self, creation_t: UnixMoment) {
         self.0.retain(|a| a.creation_t != creation_t && a.is_running());
     }
+    /// removes the pending alarms that were synced from the calendar directory
+    pub fn remove_managed(&mut self) {
+        self.0.retain(|a| ! a.managed);
+    }
     pub fn acknowledge(&mut self) {
         self.1.clear();
     }
+    /// acknowledges one ringing alarm, returns false if it is not ringing
+    pub fn acknowledge_alarm(&mut self, creation_t: UnixMoment) -> bool {
+        let before = self.1.len();
+        self.1.retain(|a| a.creation_t != creation_t);
+        self.1.len() != before
+    }
+    /// Acknowledges a ringing alarm and adds a copy of it that goes off at
+    /// `until`. The copy does not repeat, the next occurrence is already
+    /// pending. Returns false if the alarm is not ringing.
+    pub fn snooze(&mut self, creation_t: UnixMoment, now: UnixMoment, until: UnixMoment) -> bool {
+        let Some(i) = self.1.iter().position(|a| a.creation_t == creation_t) else { return false };
+        let mut a = self.1.remove(i);
+        a.creation_t = now;
+        a.end_t = until;
+        a.state = AlarmState::Waiting;
+        a.recurrence = None;
+        a.daily = None;
+        a.uid = None;
+        a.managed = false;
+        a.warned = true;
+        self.add(a);
+        true
+    }
     pub fn lead_alarming(&self) -> Option<&Alarm> {
         self.1.first()
     }
@@ -116,6 +225,25 @@ impl AlarmList {
     pub fn alarming_len(&self) -> usize {
         self.1.len()
     }
+    /// Marks the pending alarms that go off within `before` as warned and
+    /// returns them. Alarms made after their warning time are not warned.
+    pub fn take_warnings(&mut self, now: UnixMoment, before: Duration) -> Vec<Alarm> {
+        self.0.iter_mut()
+            .rev()
+            .filter(|a| ! a.warned && a.creation_t < a.end_t - before && a.end_t - before <= now && now < a.end_t)
+            .map(|a| {
+                a.warned = true;
+                a.clone()
+            })
+            .collect()
+    }
+    /// When the next warning is due, for alarms that go off `before` later
+    pub fn next_warning(&self, before: Duration) -> Option<UnixMoment> {
+        self.0.iter()
+            .filter(|a| ! a.warned && a.creation_t < a.end_t - before)
+            .map(|a| a.end_t - before)
+            .min()
+    }
     fn promote_next(&mut self) {
         if let Some(a) = self.0.pop() {
             self.1.push(a);
@@ -124,7 +252,8 @@ impl AlarmList {
     pub fn update_with_current_time(&mut self, now: UnixMoment) -> Vec<AlarmEvent> {
         let mut events = Vec::new();
         let mut promote_counter = 0;
-        for a in self.0.iter_mut() {
+        // earliest first, the list is sorted latest first
+        for a in self.0.iter_mut().rev() {
             if let Some(ev) = a.update_with_current_time(now) {
                 if matches!(ev, AlarmEvent::Now(_)) { promote_counter += 1; };
                 events.push(ev);
@@ -27,6 +27,14 @@ impl UnixMoment {
     pub fn seconds(&self) -> i64 {
         self.0
     }
+    /// nanoseconds from UNIX_EPOCH, precise enough to tell alarms apart by
+    /// their creation time
+    pub fn as_nanos(&self) -> i64 {
+        self.0 * NANOSECONDS_PER_SECOND + self.1
+    }
+    pub fn from_nanos(n: i64) -> Self {
+        Self(n.div_euclid(NANOSECONDS_PER_SECOND), n.rem_euclid(NANOSECONDS_PER_SECOND))
+    }
     fn next_unit(&self, unit_seconds: i64) -> Self {
         let s = self.0 + unit_seconds - self.0 % unit_seconds;
         Self(s, 0)
@@ -0,0 +1,215 @@
+use crate::*;
+use tz::TimeZoneRef;
+
+/// What a command changed, to tell the listeners about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applied {
+    AlarmList,
+    Ack,
+    AckAlarm(UnixMoment),
+    Snooze(UnixMoment),
+    SwitchDisplay,
+    Chimes(bool),
+    DoNotDisturb(bool),
+}
+
+impl Applied {
+    pub fn event(self) -> AppEvent {
+        match self {
+            Applied::AlarmList => AppEvent::AlarmListUpdate,
+            Applied::Ack => AppEvent::Ack,
+            Applied::AckAlarm(id) => AppEvent::AckAlarm(id),
+            Applied::Snooze(id) => AppEvent::Snooze(id),
+            Applied::SwitchDisplay => AppEvent::SwitchDisplay,
+            Applied::Chimes(on) => AppEvent::Chimes(on),
+            Applied::DoNotDisturb(on) => AppEvent::DoNotDisturb(on),
+        }
+    }
+}
+
+/// Applies one command to the alarm list. Broadcasting the change is left
+/// to the caller, so that a batch is announced only once.
+pub fn apply_command(x: &mut AlarmList, cmd: AppCommand, now: UnixMoment, tz: TimeZoneRef, options: ResolveOptions) -> Result<Applied, String> {
+    apply(x, cmd, now, tz, options, false)
+}
+
+/// Turns the specs into alarms, or returns what is wrong with each bad one
+fn into_alarms(specs: Vec<AlarmSpec>, now: UnixMoment, tz: TimeZoneRef, options: ResolveOptions) -> (Vec<Alarm>, Vec<String>) {
+    let mut alarms = Vec::new();
+    let mut errors = Vec::new();
+    for spec in specs {
+        let what = spec.to_string();
+        match spec.into_alarm(now, tz, options) {
+            Ok(a) => alarms.push(a),
+            Err(e) => errors.push(format!("{what} ({e})")),
+        }
+    }
+    (alarms, errors)
+}
+
+/// A calendar sync on its own skips the alarms it cannot set, so one bad
+/// event does not hold up the rest. In a batch it fails like any command.
+fn apply(x: &mut AlarmList, cmd: AppCommand, now: UnixMoment, tz: TimeZoneRef, options: ResolveOptions, in_batch: bool) -> Result<Applied, String> {
+    match cmd {
+        AppCommand::Add(spec) => {
+            let a = spec.into_alarm(now, tz, options).map_err(|e| format!("add: bad alarm spec: {e}"))?;
+            if ! a.managed && a.uid.is_some() && x.pending().any(|p| p.managed && p.uid == a.uid) {
+                warn!("add: {} replaces an alarm synced from the calendar, the next sync will undo this", a.name);
+            }
+            x.add(a);
+            Ok(Applied::AlarmList)
+        },
+        AppCommand::ImportIcs(path) => {
+            let import = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| import_ics(&text, now, tz).map_err(|e| e.to_string()))
+                .map_err(|e| format!("import {path:?}: {e}"))?;
+            for w in import.warnings.iter() {
+                warn!("import {path:?}: {w}");
+            }
+            let (alarms, errors) = into_alarms(import.specs, now, tz, options);
+            if ! errors.is_empty() {
+                return Err(format!("import {path:?}: bad alarm specs, nothing was imported: {}", errors.join("; ")));
+            }
+            for a in alarms {
+                x.add(a);
+            }
+            Ok(Applied::AlarmList)
+        },
+        AppCommand::Acknowledge => {
+            x.acknowledge();
+            Ok(Applied::Ack)
+        },
+        AppCommand::AcknowledgeAlarm(creation_t) => {
+            if ! x.acknowledge_alarm(creation_t) {
+                return Err(format!("ack: no ringing alarm {}", creation_t.as_nanos()));
+            }
+            // the last one is the same as acknowledging all
+            if x.alarming_len() == 0 {
+                Ok(Applied::Ack)
+            } else {
+                Ok(Applied::AckAlarm(creation_t))
+            }
+        },
+        AppCommand::Snooze(creation_t, wait) => {
+            if ! x.snooze(creation_t, now, now + wait) {
+                return Err(format!("snooze: no ringing alarm {}", creation_t.as_nanos()));
+            }
+            Ok(Applied::Snooze(creation_t))
+        },
+        AppCommand::Remove(creation_t) => {
+            let Some(a) = x.pending().find(|a| a.creation_t == creation_t) else {
+                return Err(format!("remove: no pending alarm {}", creation_t.as_nanos()));
+            };
+            if a.managed {
+                warn!("remove: {} is synced from the calendar, the next sync will bring it back", a.name);
+            }
+            x.remove(creation_t);
+            Ok(Applied::AlarmList)
+        },
+        AppCommand::SyncManaged(specs) => {
+            let specs = specs.into_iter().map(AlarmSpec::managed).collect();
+            let (alarms, errors) = into_alarms(specs, now, tz, options);
+            if in_batch && ! errors.is_empty() {
+                return Err(format!("sync: bad alarm specs: {}", errors.join("; ")));
+            }
+            for e in errors {
+                error!("sync: bad alarm spec: {e}");
+            }
+            x.remove_managed();
+            for a in alarms {
+                x.add(a);
+            }
+            Ok(Applied::AlarmList)
+        },
+        AppCommand::SwitchDisplay => Ok(Applied::SwitchDisplay),
+        AppCommand::Chimes(on) => Ok(Applied::Chimes(on)),
+        AppCommand::DoNotDisturb(on) => Ok(Applied::DoNotDisturb(on)),
+        AppCommand::List => Err(String::from("list is answered by the command socket")),
+        AppCommand::Batch(_) => Err(String::from("batches cannot be nested")),
+    }
+}
+
+/// Applies the commands to a copy of the alarm list and keeps the copy only
+/// if all of them succeed. Returns the outcome of each command and the
+/// changes to announce, each at most once.
+pub fn apply_batch(x: &mut AlarmList, cmds: Vec<AppCommand>, now: UnixMoment, tz: TimeZoneRef, options: ResolveOptions) -> (Vec<CommandOutcome>, Vec<Applied>) {
+    let mut staged = x.clone();
+    let mut changes = Vec::new();
+    // a nanosecond apart, so that alarms snoozed together keep distinct ids
+    let results: Vec<Result<Applied, String>> = cmds.into_iter()
+        .enumerate()
+        .map(|(i, cmd)| apply(&mut staged, cmd, now + Duration::from_nanos(i as u64), tz, options, true))
+        .collect();
+    let ok = results.iter().all(Result::is_ok);
+    let outcomes = results.into_iter().map(|r| match r {
+        Ok(applied) if ok => {
+            if ! changes.contains(&applied) { changes.push(applied); }
+            CommandOutcome::Done
+        },
+        Ok(_) => CommandOutcome::NotApplied,
+        Err(e) => CommandOutcome::Failed(e),
+    }).collect();
+    if ok {
+        *x = staged;
+    }
+    (outcomes, changes)
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+    use crate::fixture::*;
+
+    #[test]
+    fn batch_is_all_or_nothing() {
+        let tz = eastern();
+        let now = UnixMoment::new(NOW);
+        let add = |expr: &str| AppCommand::Add(Box::new(AlarmSpec::from_expr(String::from("a"), None, expr.to_owned())));
+        let mut x = AlarmList::new();
+        let (outcomes, changes) = apply_batch(&mut x, vec![add("13:00"), add("bogus"), AppCommand::SwitchDisplay], now, tz.as_ref(), ResolveOptions::default());
+        assert_eq!(CommandOutcome::NotApplied, outcomes[0]);
+        assert!(matches!(outcomes[1], CommandOutcome::Failed(_)));
+        assert!(changes.is_empty());
+        assert_eq!(0, x.pending_len());
+        let (outcomes, changes) = apply_batch(&mut x, vec![add("13:00"), add("14:00")], now, tz.as_ref(), ResolveOptions::default());
+        assert_eq!(vec![CommandOutcome::Done, CommandOutcome::Done], outcomes);
+        assert_eq!(vec![Applied::AlarmList], changes);
+        assert_eq!(2, x.pending_len());
+    }
+
+    #[test]
+    fn bad_specs_fail_in_batch() {
+        let tz = eastern();
+        let now = UnixMoment::new(NOW);
+        let spec = |expr: &str| AlarmSpec::from_expr(String::from("a"), None, expr.to_owned());
+        let sync = || AppCommand::SyncManaged(vec![spec("13:00"), spec("bogus")]);
+        let mut x = AlarmList::new();
+        let (outcomes, _) = apply_batch(&mut x, vec![sync()], now, tz.as_ref(), ResolveOptions::default());
+        assert!(matches!(outcomes[0], CommandOutcome::Failed(_)));
+        assert_eq!(0, x.pending_len());
+        // on its own the sync keeps the good alarm
+        apply_command(&mut x, sync(), now, tz.as_ref(), ResolveOptions::default()).unwrap();
+        assert_eq!(1, x.pending_len());
+    }
+
+    #[test]
+    fn snoozes_in_batch_keep_apart() {
+        let tz = eastern();
+        let now = UnixMoment::new(NOW);
+        let mut x = AlarmList::new();
+        for (name, id) in [("tea", 1), ("bread", 2)] {
+            let mut a = Alarm::new_from_unixmoment(name, None, now);
+            a.creation_t = UnixMoment::new(NOW - 3600 + id);
+            x.add(a);
+        }
+        x.update_with_current_time(now);
+        assert_eq!(2, x.alarming_len());
+        let snooze = |id| AppCommand::Snooze(UnixMoment::new(NOW - 3600 + id), Duration::from_secs(300));
+        let (outcomes, _) = apply_batch(&mut x, vec![snooze(1), snooze(2)], now, tz.as_ref(), ResolveOptions::default());
+        assert_eq!(vec![CommandOutcome::Done, CommandOutcome::Done], outcomes);
+        let ids: Vec<UnixMoment> = x.pending().map(|a| a.creation_t).collect();
+        assert_eq!(2, ids.len());
+        assert_ne!(ids[0], ids[1]);
+    }
+}
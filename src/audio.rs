@@ -1,36 +1,542 @@
 use crate::*;
 
-fn make_audio_command(soundfile: PathBuf) -> Command {
-    let mut c = Command::new("/usr/bin/mpv");
-    c.arg("--no-terminal")
-        .arg("--no-video")
-        .arg(soundfile);
-    c
-}
-
-pub async fn start_audio_task(mut event_rx: broadcast::Receiver<AppEvent>) {
-    let mut child: Option<Child> = None;
-    while let Some(ev) = event_rx.next().await {
-        match ev {
-            AppEvent::Ring(Alarm { soundfile: Some(soundfile), .. }) => {
-                if child.is_none() {
-                    let mut cmd = make_audio_command(soundfile.into());
-                    let spawn_result = cmd.spawn();
-                    if let Ok(ch) = spawn_result {
-                        child = Some(ch);
-                    } else {
-                        error!("Could not start audio process: {spawn_result:?}");
-                    }
+/// How the audio task plays sounds
+pub struct AudioConfig {
+    /// the player's full path and template, or why there is none
+    pub player: Result<(std::path::PathBuf, PlayerTemplate), String>,
+    pub volume: u8,
+    pub loops: u32,
+    pub queue: SoundQueue,
+    /// how long to ramp the volume up from a start level, over mpv's IPC
+    pub fade: Option<(u8, Duration)>,
+    pub speech: Option<Speech>,
+    pub chimes: Option<Chimes>,
+}
+
+/// Chimes the time of day while no alarm is ringing
+pub struct Chimes {
+    pub config: ChimeConfig,
+    /// to look up the chime sounds, without the default and fallback
+    pub sounds: SoundLibrary,
+    pub volume: u8,
+    /// chiming when the daemon starts
+    pub on: bool,
+}
+
+/// When the alarm is spoken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SpeechTiming {
+    /// once, before the sound first plays
+    Before,
+    /// before every loop of the sound
+    Between,
+}
+
+/// Announces alarms with a text to speech command
+pub struct Speech {
+    program: std::path::PathBuf,
+    template: SpeechTemplate,
+    /// see speech_text
+    text: String,
+    /// for AppEvent::Warn, which is not spoken without it
+    warning_text: Option<String>,
+    timing: SpeechTiming,
+    tz: tz::TimeZone,
+}
+
+impl Speech {
+    /// A preset, "auto" for the first preset on PATH, or a template
+    pub fn new(command: &str, text: String, warning_text: Option<String>, timing: SpeechTiming) -> Option<Self> {
+        let path = std::env::var_os("PATH");
+        let template = match command {
+            "auto" => SpeechTemplate::probe(path.as_deref()),
+            c => SpeechTemplate::from_config(c),
+        };
+        let speech = template.and_then(|t| t.locate(path.as_deref()).map(|program| (program, t)));
+        let tz = tz::TimeZone::local().map_err(|e| error!("Could not get the local time zone: {e}")).ok()?;
+        match speech {
+            Ok((program, template)) => {
+                info!("speech: {template} ({program:?})");
+                Some(Self { program, template, text, warning_text, timing, tz })
+            },
+            Err(e) => {
+                error!("speech: {e}");
+                None
+            },
+        }
+    }
+
+    fn spawn(&self, text: &str) -> Result<Child, String> {
+        use async_std::process::Stdio;
+        use async_std::io::WriteExt;
+        let on_stdin = self.template.text_on_stdin();
+        let mut child = Command::new(&self.program)
+            .args(self.template.args_for(text))
+            .stdin(if on_stdin { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not start speech process: {e}"))?;
+        if let Some(stderr) = child.stderr.take() {
+            spawn(log_stderr("speech", stderr));
+        }
+        if let Some(mut stdin) = child.stdin.take() {
+            let text = format!("{text}\n");
+            spawn(async move { stdin.write_all(text.as_bytes()).await });
+        }
+        Ok(child)
+    }
+}
+
+impl AudioConfig {
+    /// Uses the configured player, or the first preset found on PATH
+    pub fn new(player: Option<&str>, volume: u8, loops: u32, queue: SoundQueue, fade: Option<(u8, Duration)>) -> Self {
+        let path = std::env::var_os("PATH");
+        let player = match player {
+            Some(p) => PlayerTemplate::from_config(p),
+            None => PlayerTemplate::probe(path.as_deref()),
+        }
+            .and_then(|t| t.locate(path.as_deref()).map(|program| (program, t)))
+            .map_err(|e| e.to_string());
+        match player {
+            Ok((ref program, ref t)) => info!("audio player: {t} ({program:?})"),
+            Err(ref e) => error!("{e}"),
+        }
+        if let (Some(_), Ok((_, ref t))) = (fade, &player) {
+            if ! t.uses_ipc() {
+                warn!("fading in needs a player with an {{ipc}} socket, like mpv");
+            }
+        }
+        Self { player, volume, loops, queue, fade, speech: None, chimes: None }
+    }
+
+    pub fn with_speech(mut self, speech: Option<Speech>) -> Self {
+        self.speech = speech;
+        self
+    }
+
+    pub fn with_chimes(mut self, chimes: Chimes) -> Self {
+        self.chimes = Some(chimes);
+        self
+    }
+
+    /// The fade of the player, if it has an IPC socket
+    fn fade(&self) -> Option<mpv::Fade> {
+        let (_, template) = self.player.as_ref().ok()?;
+        let (from, over) = self.fade.filter(|_| template.uses_ipc())?;
+        Some(mpv::Fade { from: from.min(self.volume), to: self.volume, over })
+    }
+
+    fn make_audio_command(&self, soundfile: &std::path::Path, volume: u8, loops: u32, ipc: Option<&std::path::Path>) -> Result<Command, String> {
+        let (program, template) = self.player.as_ref().map_err(String::clone)?;
+        let mut c = Command::new(program);
+        c.args(template.args_for(soundfile, volume, loops, ipc));
+        Ok(c)
+    }
+}
+
+/// A fresh path for a player's IPC socket, in $XDG_RUNTIME_DIR if there
+/// is one as that is private to the user
+fn ipc_socket_path() -> std::path::PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|d| ! d.is_empty())
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let n = COUNT.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!("clockd-mpv-{}-{n}.sock", std::process::id()))
+}
+
+/// restarts of a failing player before its sound is given up
+const MAX_RESTARTS: u32 = 2;
+/// wait before restarting a player that failed
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+/// a looping sound is played at most this often, for players that exit
+/// right away
+const MIN_LOOP_TIME: Duration = Duration::from_secs(1);
+/// wait for a player's IPC socket before giving up on it
+const IPC_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How the sounds of several ringing alarms take turns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SoundQueue {
+    /// play each alarm's sound to the end, looping ones until acknowledged,
+    /// before the next
+    InOrder,
+    /// play each alarm's sound once in turn, looping ones come around again
+    Rotate,
+}
+
+/// The player of the sound at the front of the queue
+enum Player {
+    Idle,
+    /// `ipc` is the player's socket, if it has one
+    Playing { child: Child, ipc: Option<std::path::PathBuf>, restarts: u32, started: Instant },
+    /// waiting to start the player again
+    Restarting { restarts: u32, timer: Timer },
+    /// announcing the front of the queue, whose sound plays after, or a
+    /// warning
+    Speaking { child: Child, then_play: bool },
+    /// striking the hour, `strikes` more times after this one
+    Chiming { child: Child, ipc: Option<std::path::PathBuf>, file: std::path::PathBuf, strikes: u32 },
+}
+
+enum Input {
+    Event(Box<AppEvent>),
+    Exited(std::io::Result<std::process::ExitStatus>),
+    Restart,
+}
+
+/// Broadcasts from a separate task, as waiting here while our own receiver
+/// is full would never finish
+fn announce(event_tx: &broadcast::Sender<AppEvent>, ev: AppEvent) {
+    let tx = event_tx.clone();
+    spawn(async move { tx.broadcast(ev).await });
+}
+
+async fn log_stderr(who: &'static str, stderr: async_std::process::ChildStderr) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Some(Ok(line)) = lines.next().await {
+        warn!("{who}: {line}");
+    }
+}
+
+impl AudioConfig {
+    /// Starts the player, quietly if it fades in. Players with an {ipc}
+    /// placeholder get a socket, which is returned with the child.
+    fn spawn_player(&self, soundfile: &std::path::Path) -> Result<(Child, Option<std::path::PathBuf>), String> {
+        let fade = self.fade();
+        let volume = fade.map(|f| f.from).unwrap_or(self.volume);
+        self.spawn_player_with(soundfile, volume, self.loops, fade)
+    }
+
+    /// Starts the player for one play of a chime
+    fn spawn_chime(&self, soundfile: &std::path::Path) -> Result<(Child, Option<std::path::PathBuf>), String> {
+        let volume = self.chimes.as_ref().map(|c| c.volume).unwrap_or(self.volume);
+        self.spawn_player_with(soundfile, volume, 1, None)
+    }
+
+    fn spawn_player_with(&self, soundfile: &std::path::Path, volume: u8, loops: u32, fade: Option<mpv::Fade>) -> Result<(Child, Option<std::path::PathBuf>), String> {
+        use async_std::process::Stdio;
+        let uses_ipc = self.player.as_ref().map(|(_, t)| t.uses_ipc()).unwrap_or(false);
+        let ipc = uses_ipc.then(ipc_socket_path);
+        let mut cmd = self.make_audio_command(soundfile, volume, loops, ipc.as_deref())?;
+        cmd.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::piped());
+        let mut child = cmd.spawn().map_err(|e| format!("Could not start audio process: {e}"))?;
+        if let Some(stderr) = child.stderr.take() {
+            spawn(log_stderr("player", stderr));
+        }
+        if let (Some(fade), Some(socket)) = (fade, ipc.clone()) {
+            spawn(async move {
+                if let Err(e) = mpv::fade_in(&socket, fade, IPC_TIMEOUT).await {
+                    debug!("fade in stopped: {e}");
+                }
+            });
+        }
+        Ok((child, ipc))
+    }
+}
+
+struct Supervisor {
+    config: AudioConfig,
+    event_tx: broadcast::Sender<AppEvent>,
+    /// ringing alarms with a sound, the front one is playing
+    queue: std::collections::VecDeque<Alarm>,
+    player: Player,
+    state: PlaybackState,
+    /// alarms of the queue that were announced
+    spoken: Vec<UnixMoment>,
+    chimes_on: bool,
+    do_not_disturb: bool,
+}
+
+impl Supervisor {
+    fn set_state(&mut self, state: PlaybackState) {
+        if state != self.state {
+            debug!("playback: {state:?}");
+            self.state = state;
+            announce(&self.event_tx, AppEvent::Playback(state));
+        }
+    }
+
+    /// Announces the front of the queue if it is its turn, or starts its
+    /// sound
+    fn play(&mut self, restarts: u32) {
+        let queue = &self.queue;
+        self.spoken.retain(|id| queue.iter().any(|a| a.creation_t == *id));
+        let Some(alarm) = self.queue.front() else {
+            self.player = Player::Idle;
+            self.set_state(PlaybackState::Idle);
+            return;
+        };
+        let speech = self.config.speech.as_ref()
+            .filter(|s| restarts == 0 && (s.timing == SpeechTiming::Between || ! self.spoken.contains(&alarm.creation_t)));
+        if let Some(speech) = speech {
+            let text = speech_text(&speech.text, alarm, UnixMoment::now(), speech.tz.as_ref());
+            self.spoken.push(alarm.creation_t);
+            match speech.spawn(&text) {
+                Ok(child) => {
+                    self.player = Player::Speaking { child, then_play: true };
+                    self.set_state(PlaybackState::Playing);
+                    return;
+                },
+                Err(e) => warn!("{e}"),
+            }
+        }
+        self.play_sound(restarts);
+    }
+
+    /// Starts the player for the front of the queue
+    fn play_sound(&mut self, restarts: u32) {
+        let Some(alarm) = self.queue.front() else { return self.play(0) };
+        let Some(soundfile) = alarm.soundfile.clone() else {
+            // only announced
+            self.queue.pop_front();
+            return self.play(0);
+        };
+        match self.config.spawn_player(&soundfile) {
+            Ok((child, ipc)) => {
+                self.player = Player::Playing { child, ipc, restarts, started: Instant::now() };
+                self.set_state(PlaybackState::Playing);
+            },
+            Err(e) => self.failed(restarts, e),
+        }
+    }
+
+    /// Plays the front of the queue again after `wait`
+    fn play_after(&mut self, wait: Duration, restarts: u32) {
+        if self.queue.is_empty() {
+            self.player = Player::Idle;
+            self.set_state(PlaybackState::Idle);
+        } else {
+            self.player = Player::Restarting { restarts, timer: Timer::after(wait) };
+        }
+    }
+
+    /// Tries again after a delay, or gives up on the sound after
+    /// MAX_RESTARTS and goes on with the next
+    fn failed(&mut self, restarts: u32, e: String) {
+        if restarts < MAX_RESTARTS && self.config.player.is_ok() {
+            warn!("{e}, trying again");
+            self.play_after(RESTART_DELAY, restarts + 1);
+            return;
+        }
+        error!("{e}");
+        self.queue.pop_front();
+        self.player = Player::Idle;
+        self.set_state(PlaybackState::Failed);
+        announce(&self.event_tx, AppEvent::AudioError(e));
+        if ! self.queue.is_empty() {
+            self.play(0);
+        }
+    }
+
+    fn exited(&mut self, status: std::io::Result<std::process::ExitStatus>) {
+        if let Player::Speaking { then_play, .. } = self.player {
+            match status {
+                Ok(s) if ! s.success() => warn!("speech failed with {s}"),
+                Err(e) => warn!("Could not wait for the speech process: {e}"),
+                _ => {},
+            }
+            self.player = Player::Idle;
+            return if then_play { self.play_sound(0) } else { self.play(0) };
+        }
+        if let Player::Chiming { ipc, file, strikes, .. } = std::mem::replace(&mut self.player, Player::Idle) {
+            if let Some(socket) = ipc {
+                std::fs::remove_file(socket).ok();
+            }
+            match status {
+                Ok(s) if s.success() && strikes > 0 => return self.chime(file, strikes - 1),
+                Ok(s) if ! s.success() => warn!("chime: audio player failed with {s}"),
+                Err(e) => warn!("chime: could not wait for the audio player: {e}"),
+                _ => {},
+            }
+            return self.play(0);
+        }
+        let Player::Playing { restarts, started, ipc, .. } = std::mem::replace(&mut self.player, Player::Idle) else { return };
+        if let Some(socket) = ipc {
+            std::fs::remove_file(socket).ok();
+        }
+        match status {
+            Ok(s) if s.success() => {
+                let looping = self.queue.front().map(|a| a.sound_policy == SoundPolicy::Loop).unwrap_or(false);
+                match (looping, self.config.queue) {
+                    (false, _) => { self.queue.pop_front(); },
+                    (true, SoundQueue::InOrder) => {},
+                    (true, SoundQueue::Rotate) => self.queue.rotate_left(1),
                 }
+                // only wait when the same sound comes around again
+                let wait = if looping && self.queue.len() == 1 {
+                    MIN_LOOP_TIME.saturating_sub(started.elapsed())
+                } else {
+                    Duration::ZERO
+                };
+                self.play_after(wait, 0);
+            },
+            Ok(s) => self.failed(restarts, format!("Audio player failed with {s}")),
+            Err(e) => self.failed(restarts, format!("Could not wait for the audio player: {e}")),
+        }
+    }
+
+    /// Asks the player to stop over its IPC socket, or kills it
+    async fn stop_player(&mut self) {
+        let (mut child, ipc) = match std::mem::replace(&mut self.player, Player::Idle) {
+            Player::Playing { child, ipc, .. } => (child, ipc),
+            Player::Speaking { child, .. } => (child, None),
+            Player::Chiming { child, ipc, .. } => (child, ipc),
+            _ => return,
+        };
+        if let Some(socket) = ipc {
+            if let Err(e) = mpv::stop(&socket, IPC_TIMEOUT).await {
+                debug!("could not stop the player over IPC: {e}");
+                child.kill();
+            }
+            std::fs::remove_file(socket).ok();
+        } else {
+            child.kill();
+        }
+        child.status().await;
+    }
+
+    async fn ring(&mut self, alarm: Alarm) {
+        if alarm.soundfile.is_none() && self.config.speech.is_none() {
+            return;
+        }
+        // alarms cut chimes short
+        if matches!(self.player, Player::Chiming { .. }) {
+            self.stop_player().await;
+        }
+        self.queue.push_back(alarm);
+        if matches!(self.player, Player::Idle) {
+            self.play(0);
+        }
+    }
+
+    /// Removes one alarm's sound, stopping it if it is playing
+    async fn acknowledge(&mut self, creation_t: UnixMoment) {
+        let Some(i) = self.queue.iter().position(|a| a.creation_t == creation_t) else { return };
+        self.queue.remove(i);
+        if i == 0 {
+            self.stop_player().await;
+            self.play(0);
+        }
+    }
+
+    /// Chimes for the time of day, unless something is playing
+    fn minute(&mut self, dt: tz::DateTime) {
+        let Some(ref chimes) = self.config.chimes else { return };
+        if ! self.chimes_on || self.do_not_disturb || ! matches!(self.player, Player::Idle) {
+            return;
+        }
+        let Some(chime) = chimes.config.chime_at(dt.hour(), dt.minute()) else { return };
+        let (file, warning) = chimes.sounds.sound_for(Some(chime.sound.as_ref()), SoundSelection::Random, 0);
+        if let Some(w) = warning {
+            warn!("chime: {w}");
+        }
+        if let Some(file) = file {
+            self.chime(file, chime.strikes.saturating_sub(1));
+        }
+    }
+
+    fn chime(&mut self, file: std::path::PathBuf, strikes: u32) {
+        match self.config.spawn_chime(&file) {
+            Ok((child, ipc)) => {
+                self.player = Player::Chiming { child, ipc, file, strikes };
+                self.set_state(PlaybackState::Playing);
+            },
+            Err(e) => {
+                warn!("chime: {e}");
+                self.play(0);
+            },
+        }
+    }
+
+    /// Turns chimes or do not disturb on or off, stopping a chime that
+    /// should not play
+    async fn switch_chimes(&mut self, chimes_on: bool, do_not_disturb: bool) {
+        self.chimes_on = chimes_on;
+        self.do_not_disturb = do_not_disturb;
+        if (! chimes_on || do_not_disturb) && matches!(self.player, Player::Chiming { .. }) {
+            self.stop_player().await;
+            self.play(0);
+        }
+    }
+
+    /// Speaks a warning, unless something is playing
+    fn warn(&mut self, alarm: &Alarm) {
+        let Some(speech) = self.config.speech.as_ref() else { return };
+        let Some(ref template) = speech.warning_text else { return };
+        if ! matches!(self.player, Player::Idle) {
+            debug!("not speaking the warning for {} while playing", alarm.name);
+            return;
+        }
+        match speech.spawn(&speech_text(template, alarm, UnixMoment::now(), speech.tz.as_ref())) {
+            Ok(child) => {
+                self.player = Player::Speaking { child, then_play: false };
+                self.set_state(PlaybackState::Playing);
+            },
+            Err(e) => warn!("{e}"),
+        }
+    }
+
+    async fn acknowledge_all(&mut self) {
+        self.queue.clear();
+        self.stop_player().await;
+        self.set_state(PlaybackState::Idle);
+    }
+
+    /// Waits for the player to exit or to be restarted
+    async fn next_change(&mut self) -> Input {
+        match self.player {
+            Player::Playing { ref mut child, .. }
+                | Player::Speaking { ref mut child, .. }
+                | Player::Chiming { ref mut child, .. } => Input::Exited(child.status().await),
+            Player::Restarting { ref mut timer, .. } => {
+                timer.await;
+                Input::Restart
+            },
+            Player::Idle => std::future::pending().await,
+        }
+    }
+}
+
+/// Plays the sound of ringing alarms and watches the player
+pub async fn start_audio_task(mut event_rx: broadcast::Receiver<AppEvent>, event_tx: broadcast::Sender<AppEvent>, config: AudioConfig) {
+    use futures_lite::FutureExt;
+    let chimes_on = config.chimes.as_ref().map(|c| c.on).unwrap_or(false);
+    let mut s = Supervisor {
+        config,
+        event_tx,
+        queue: Default::default(),
+        player: Player::Idle,
+        state: PlaybackState::Idle,
+        spoken: Vec::new(),
+        chimes_on,
+        do_not_disturb: false,
+    };
+    loop {
+        let input = async { event_rx.next().await.map(|ev| Input::Event(Box::new(ev))) }
+            .or(async { Some(s.next_change().await) })
+            .await;
+        match input {
+            None => break,
+            Some(Input::Event(ev)) => match *ev {
+                AppEvent::Ring(alarm) => s.ring(*alarm).await,
+                AppEvent::Minute(dt) => s.minute(dt),
+                AppEvent::Chimes(on) => s.switch_chimes(on, s.do_not_disturb).await,
+                AppEvent::DoNotDisturb(on) => s.switch_chimes(s.chimes_on, on).await,
+                AppEvent::Warn(alarm) => s.warn(&alarm),
+                AppEvent::Ack => s.acknowledge_all().await,
+                AppEvent::AckAlarm(creation_t) | AppEvent::Snooze(creation_t) => s.acknowledge(creation_t).await,
+                _ => {},
             },
-            AppEvent::Ack => {
-                if let Some(mut child) = child.take() {
-                    child.kill();
-                    child.status().await;
+            Some(Input::Exited(status)) => s.exited(status),
+            Some(Input::Restart) => {
+                if let Player::Restarting { restarts, .. } = s.player {
+                    s.play(restarts);
                 }
             },
-            _ => { continue; }
         }
     }
-    unreachable!()
 }
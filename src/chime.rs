@@ -0,0 +1,119 @@
+use serde::{Serialize, Deserialize};
+
+/// How often the clock chimes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum)]
+pub enum ChimeInterval {
+    #[default]
+    Hour,
+    HalfHour,
+    Quarter,
+}
+
+/// A daily span without chimes, e.g. "22:00-07:00"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    /// minutes after midnight
+    start: u32,
+    end: u32,
+}
+
+#[derive(Debug)]
+pub struct QuietHoursError(String);
+impl std::error::Error for QuietHoursError {}
+impl std::fmt::Display for QuietHoursError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Quiet hours should look like 22:00-07:00, got {}", self.0)
+    }
+}
+
+impl std::str::FromStr for QuietHours {
+    type Err = QuietHoursError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || QuietHoursError(s.to_owned());
+        let minutes = |hm: &str| -> Option<u32> {
+            let (h, m) = hm.trim().split_once(':')?;
+            let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+            (h < 24 && m < 60).then_some(h * 60 + m)
+        };
+        let (start, end) = s.split_once('-').ok_or_else(err)?;
+        Ok(Self { start: minutes(start).ok_or_else(err)?, end: minutes(end).ok_or_else(err)? })
+    }
+}
+
+impl QuietHours {
+    /// The span covers this time of day, it may wrap around midnight
+    pub fn contains(&self, hour: u8, minute: u8) -> bool {
+        let t = hour as u32 * 60 + minute as u32;
+        if self.start <= self.end {
+            self.start <= t && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+}
+
+/// Which sounds the clock chimes with, and when
+#[derive(Debug, Clone)]
+pub struct ChimeConfig {
+    pub interval: ChimeInterval,
+    /// sound name or path for the hour
+    pub hour_sound: String,
+    pub half_sound: Option<String>,
+    pub quarter_sound: Option<String>,
+    /// play the hour sound once for each hour, 1 to 12
+    pub strike: bool,
+    pub quiet: Option<QuietHours>,
+}
+
+/// A chime to play: the sound and how many times
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chime {
+    pub sound: String,
+    pub strikes: u32,
+}
+
+impl ChimeConfig {
+    /// The chime for this time of day, if the clock chimes then
+    pub fn chime_at(&self, hour: u8, minute: u8) -> Option<Chime> {
+        if self.quiet.map(|q| q.contains(hour, minute)).unwrap_or(false) {
+            return None;
+        }
+        let half = || self.half_sound.as_ref().unwrap_or(&self.hour_sound);
+        let (sound, strikes) = match (minute, self.interval) {
+            (0, _) if self.strike => (&self.hour_sound, (hour as u32 + 11) % 12 + 1),
+            (0, _) => (&self.hour_sound, 1),
+            (30, ChimeInterval::HalfHour | ChimeInterval::Quarter) => (half(), 1),
+            (15 | 45, ChimeInterval::Quarter) => (self.quarter_sound.as_ref().unwrap_or_else(half), 1),
+            _ => return None,
+        };
+        Some(Chime { sound: sound.clone(), strikes })
+    }
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+
+    #[test]
+    fn chimes_on_time() {
+        let mut c = ChimeConfig {
+            interval: ChimeInterval::HalfHour,
+            hour_sound: String::from("cuckoo"),
+            half_sound: Some(String::from("ding")),
+            quarter_sound: None,
+            strike: true,
+            quiet: Some("22:00-07:00".parse().unwrap()),
+        };
+        assert_eq!(Some(Chime { sound: String::from("cuckoo"), strikes: 3 }), c.chime_at(15, 0));
+        assert_eq!(Some(Chime { sound: String::from("cuckoo"), strikes: 12 }), c.chime_at(12, 0));
+        assert_eq!(Some(Chime { sound: String::from("ding"), strikes: 1 }), c.chime_at(9, 30));
+        assert_eq!(None, c.chime_at(9, 15));
+        assert_eq!(None, c.chime_at(23, 0));
+        assert_eq!(None, c.chime_at(6, 30));
+        assert!(c.chime_at(7, 0).is_some());
+        c.interval = ChimeInterval::Quarter;
+        assert_eq!(Some(String::from("ding")), c.chime_at(9, 45).map(|c| c.sound));
+        assert!("7:00-25:00".parse::<QuietHours>().is_err());
+    }
+}
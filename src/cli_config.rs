@@ -14,6 +14,117 @@ pub struct Config {
     /// path to the unix socket for fd passing (stdout shim)
     #[clap(short = 'f', long = "fdsocket")]
     pub fd_socket: Option<PathBuf>,
+    /// file of holidays, one date per line, or an .ics calendar
+    #[clap(long = "holidays", env = "CLOCKD_HOLIDAYS")]
+    pub holidays: Option<PathBuf>,
+    /// weekdays that are working days, e.g. "mon-fri" or "sun-thu"
+    #[clap(long = "workdays", env = "CLOCKD_WORKDAYS", default_value = "mon-fri")]
+    pub workdays: String,
+    /// latitude,longitude for sunrise and sunset, e.g. "52.52,13.40"
+    #[clap(long = "location", env = "CLOCKD_LOCATION")]
+    pub location: Option<Location>,
+    /// show today's sunrise and sunset in the waybar tooltip
+    #[clap(long = "sun-tooltip", requires = "location")]
+    pub sun_tooltip: bool,
+    /// directory of .ics files in vdir layout to keep alarms in sync with
+    #[clap(long = "vdir", env = "CLOCKD_VDIR")]
+    pub vdir: Option<PathBuf>,
+    /// audio player: a preset (mpv, pw-play, paplay, ffplay, aplay) or a
+    /// command with {file}, {volume}, {volume_frac}, {volume_pa}, {loops},
+    /// {repeats} and {ipc} (an mpv --input-ipc-server socket) placeholders.
+    /// The first installed preset by default.
+    #[clap(long = "player", env = "CLOCKD_PLAYER")]
+    pub player: Option<String>,
+    /// playback volume in percent
+    #[clap(long = "volume", env = "CLOCKD_VOLUME", default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub volume: u8,
+    /// seconds to ramp the volume up over, 0 to start at full volume.
+    /// Needs a player with an {ipc} socket, like mpv.
+    #[clap(long = "fade-in", env = "CLOCKD_FADE_IN", default_value_t = 0)]
+    pub fade_in: u64,
+    /// volume in percent that fading in starts from
+    #[clap(long = "fade-start", env = "CLOCKD_FADE_START", default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub fade_start: u8,
+    /// how many times to play the sound
+    #[clap(long = "loops", env = "CLOCKD_LOOPS", default_value_t = 1)]
+    pub loops: u32,
+    /// directory of sounds that alarms can name, instead of giving a path.
+    /// $XDG_DATA_HOME/clockd/sounds by default.
+    #[clap(long = "sounds-dir", env = "CLOCKD_SOUNDS_DIR")]
+    pub sounds_dir: Option<PathBuf>,
+    /// sound name or path for alarms without a sound
+    #[clap(long = "default-sound", env = "CLOCKD_DEFAULT_SOUND", default_value = "default")]
+    pub default_sound: String,
+    /// sound name or path for alarms whose sound has gone missing, the
+    /// default sound if not set
+    #[clap(long = "fallback-sound", env = "CLOCKD_FALLBACK_SOUND")]
+    pub fallback_sound: Option<String>,
+    /// how the sounds of alarms that ring at the same time take turns
+    #[clap(long = "sound-queue", env = "CLOCKD_SOUND_QUEUE", value_enum, default_value_t = crate::audio::SoundQueue::InOrder)]
+    pub sound_queue: crate::audio::SoundQueue,
+    /// speak alarms with a text to speech command: a preset (espeak-ng,
+    /// espeak, spd-say, festival), "auto" for the first one installed, or a
+    /// command with a {text} placeholder or that reads the text on stdin
+    #[clap(long = "speak", env = "CLOCKD_SPEAK")]
+    pub speak: Option<String>,
+    /// what to say when an alarm rings, with {name}, {time}, {notes} and
+    /// {in} placeholders
+    #[clap(long = "speak-text", env = "CLOCKD_SPEAK_TEXT", default_value = "{name}")]
+    pub speak_text: String,
+    /// whether to speak once before the sound or before every loop of it
+    #[clap(long = "speak-when", env = "CLOCKD_SPEAK_WHEN", value_enum, default_value_t = crate::audio::SpeechTiming::Before)]
+    pub speak_when: crate::audio::SpeechTiming,
+    /// what to say for a warning, see --warn-before. Warnings are silent
+    /// if not set.
+    #[clap(long = "speak-warning", env = "CLOCKD_SPEAK_WARNING")]
+    pub speak_warning: Option<String>,
+    /// seconds before an alarm to send a warning
+    #[clap(long = "warn-before", env = "CLOCKD_WARN_BEFORE")]
+    pub warn_before: Option<u64>,
+    /// chime the hours from the start, see clockctl chime
+    #[clap(long = "chimes", env = "CLOCKD_CHIMES")]
+    pub chimes: bool,
+    /// how often to chime
+    #[clap(long = "chime-every", env = "CLOCKD_CHIME_EVERY", value_enum, default_value_t = ChimeInterval::Hour)]
+    pub chime_every: ChimeInterval,
+    /// sound name or path to chime the hour with
+    #[clap(long = "chime-sound", env = "CLOCKD_CHIME_SOUND", default_value = "chime")]
+    pub chime_sound: String,
+    /// sound for the half hour, the hour's sound if not set
+    #[clap(long = "chime-half-sound", env = "CLOCKD_CHIME_HALF_SOUND")]
+    pub chime_half_sound: Option<String>,
+    /// sound for the quarter hours, the half hour's sound if not set
+    #[clap(long = "chime-quarter-sound", env = "CLOCKD_CHIME_QUARTER_SOUND")]
+    pub chime_quarter_sound: Option<String>,
+    /// strike the hour, playing its sound once for each hour like a cuckoo clock
+    #[clap(long = "chime-strikes", env = "CLOCKD_CHIME_STRIKES")]
+    pub chime_strikes: bool,
+    /// chime volume in percent, the alarm volume if not set
+    #[clap(long = "chime-volume", env = "CLOCKD_CHIME_VOLUME", value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub chime_volume: Option<u8>,
+    /// no chimes during this time of day, e.g. "22:00-07:00"
+    #[clap(long = "quiet-hours", env = "CLOCKD_QUIET_HOURS")]
+    pub quiet_hours: Option<QuietHours>,
+    /// show ringing alarms as desktop notifications, with actions to
+    /// dismiss or snooze them
+    #[clap(long = "notify", env = "CLOCKD_NOTIFY")]
+    pub notify: bool,
+    /// minutes to snooze for from a notification
+    #[clap(long = "snooze", env = "CLOCKD_SNOOZE", default_value_t = 5)]
+    pub snooze: u64,
+    /// directory of hook scripts, run on alarm events: executables named
+    /// after the event (added, warned, ring, snoozed, acknowledged) or kept
+    /// in a directory named after it plus ".d". $XDG_CONFIG_HOME/clockd/hooks
+    /// by default.
+    #[clap(long = "hooks-dir", env = "CLOCKD_HOOKS_DIR")]
+    pub hooks_dir: Option<PathBuf>,
+    /// shell command to run on an alarm event, e.g. "ring=notify-send
+    /// \"$CLOCKD_ALARM_NAME\"". Can be given more than once.
+    #[clap(long = "hook")]
+    pub hooks: Vec<crate::hooks::HookSpec>,
+    /// seconds a hook may run before it is killed
+    #[clap(long = "hook-timeout", env = "CLOCKD_HOOK_TIMEOUT", default_value_t = 30)]
+    pub hook_timeout: u64,
     /// logging level
     #[clap(short = 'v', action = clap::ArgAction::Count)]
     pub verbosity: u8,
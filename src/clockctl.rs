@@ -1,20 +1,28 @@
 #![allow(unused)]
 use clockd::*;
-mod timepart;
-use timepart::TimePart;
 use std::process::exit;
 use std::time::Duration;
-use std::io::Write;
+use std::io::{Write, BufRead, BufReader};
 use std::path::{PathBuf, Path};
 use std::os::unix::net::UnixStream;
 use tz::{DateTime, TimeZone, TimeZoneRef};
-mod tz_display;
-use tz_display::*;
 
 #[derive(Debug, clap::Parser)]
 pub struct Config {
     #[clap(short = 's', long = "socket")]
     cmd_socket: Option<PathBuf>,
+    /// file of holidays, one date per line, or an .ics calendar
+    #[clap(long = "holidays", env = "CLOCKD_HOLIDAYS", global = true)]
+    holidays: Option<PathBuf>,
+    /// weekdays that are working days, e.g. "mon-fri" or "sun-thu"
+    #[clap(long = "workdays", env = "CLOCKD_WORKDAYS", default_value = "mon-fri", global = true)]
+    workdays: String,
+    /// latitude,longitude for sunrise and sunset, e.g. "52.52,13.40"
+    #[clap(long = "location", env = "CLOCKD_LOCATION", global = true)]
+    location: Option<Location>,
+    /// directory of named sounds, $XDG_DATA_HOME/clockd/sounds by default
+    #[clap(long = "sounds-dir", env = "CLOCKD_SOUNDS_DIR", global = true)]
+    sounds_dir: Option<PathBuf>,
     #[clap(subcommand)]
     subcommand: SubCommand,
 }
@@ -24,142 +32,560 @@ pub enum SubCommand {
     Add { 
         #[clap(short = 'n')]
         name: Option<String>,
+        /// sound file, directory, m3u playlist or glob, or the name of one
+        /// in the sound library
         #[clap(short = 'f')]
         soundfile: Option<PathBuf>,
+        /// how to pick a file when the sound has many
+        #[clap(long = "sound-order", value_enum, default_value_t = SoundSelection::Random)]
+        sound_order: SoundSelection,
+        /// fail instead of moving a time that already passed to its next occurrence
+        #[clap(long)]
+        strict: bool,
+        /// which time to use when a DST change skips or repeats the requested time
+        #[clap(long, value_enum, default_value_t = DstChoice::Earliest)]
+        dst: DstChoice,
+        /// repeat on a cron ("30 9 * * 1-5") or OnCalendar ("Mon..Fri 09:30") schedule
+        #[clap(short = 'r', long = "repeat")]
+        repeat: Option<String>,
+        /// repeat every day, working the time out again each day (for "sunset - 30m")
+        #[clap(long, conflicts_with = "repeat")]
+        daily: bool,
+        /// skip weekends and holidays when repeating
+        #[clap(long = "workdays-only")]
+        workdays_only: bool,
+        /// play the sound again until the alarm is acknowledged
+        #[clap(long = "loop-sound")]
+        loop_sound: bool,
+        /// free text about the alarm, for announcements
+        #[clap(long = "notes")]
+        notes: Option<String>,
+        /// label for hooks, can be given more than once
+        #[clap(short = 't', long = "tag")]
+        tags: Vec<String>,
+        /// show how the time would be resolved without adding the alarm
+        #[clap(long = "dry-run")]
+        dry_run: bool,
+        /// print the dry run as JSON
+        #[clap(long, requires = "dry_run")]
+        json: bool,
         timeparts: Vec<String>
     },
-    Ack,
+    /// acknowledge all ringing alarms, or the one with this id
+    Ack {
+        id: Option<i64>,
+    },
+    /// ring again later: all ringing alarms, or the one with this id
+    Snooze {
+        id: Option<i64>,
+        #[clap(short = 'm', long = "minutes", default_value_t = 5)]
+        minutes: u64,
+    },
+    /// print the pending and ringing alarms with their ids
     List,
     SwitchDisplay,
+    /// turn the hourly chimes on or off, see clockd --chime-every
+    Chime {
+        #[clap(value_enum)]
+        state: Switch,
+    },
+    /// keep chimes quiet, alarms still ring
+    Dnd {
+        #[clap(value_enum)]
+        state: Switch,
+    },
+    /// show when a cron or OnCalendar schedule would fire
+    NextFires {
+        schedule: String,
+        #[clap(short = 'n', default_value_t = 5)]
+        count: usize,
+    },
+    /// query the work calendar
+    #[clap(subcommand)]
+    Calendar(CalendarCommand),
+    /// the sound library
+    #[clap(subcommand)]
+    Sounds(SoundsCommand),
+    /// print the pending alarms
+    Export {
+        #[clap(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+    },
+    /// add the events and VALARMs of an .ics file, replacing earlier imports
+    /// of the same events, or alarms from a .json or .csv file made by export.
+    /// "-" reads JSON lines or CSV from stdin and adds all alarms or none.
+    Import {
+        file: PathBuf,
+        /// let the daemon read the file instead, for .ics files only
+        #[clap(long)]
+        daemon: bool,
+        /// show what would be imported without contacting the daemon
+        #[clap(long = "dry-run")]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// VEVENTs with a VALARM each
+    Ics,
+    /// one alarm per line, can be imported again
+    Json,
+    Csv,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum SoundsCommand {
+    /// print the names of the sounds
+    List,
+    /// play a sound to try it out
+    Play {
+        name: PathBuf,
+        /// player preset or command, see clockd --help
+        #[clap(long = "player", env = "CLOCKD_PLAYER")]
+        player: Option<String>,
+        /// volume in percent
+        #[clap(long = "volume", env = "CLOCKD_VOLUME", default_value_t = 100)]
+        volume: u8,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum CalendarCommand {
+    /// tell whether a date is a working day, e.g. "calendar check 2024-12-25"
+    Check {
+        date: Vec<String>,
+    },
+}
+
+/// What `add --dry-run --json` prints
+#[derive(Debug, serde::Serialize)]
+struct Preview<'a> {
+    name: &'a str,
+    expr: &'a str,
+    tokens: Vec<PreviewToken<'a>>,
+    unix_time: i64,
+    time: String,
+    remaining_seconds: i64,
+    remaining: String,
+    dst_warning: Option<String>,
+    repeats: Option<String>,
+    workdays_only: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct PreviewToken<'a> {
+    text: &'a str,
+    meaning: String,
 }
 
 type Anything<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 pub fn main() -> Anything<()> {
     let tz = tz::TimeZone::local()?;
-    let dt = DateTime::now(tz.as_ref())?;
-    let mut c: Config = clap::Parser::parse();
-    make_socket_path(&mut c.cmd_socket, "clockd.cmd")?;
-    let cmd_socket = c.cmd_socket.expect("Could not determine socket path");
-    if ! cmd_socket.exists() {
-        println!("Command socket {cmd_socket:?} does not exist");
-        exit(1);
-    }
+    let now = UnixMoment::now();
+    let c: Config = clap::Parser::parse();
+    let cmd_socket = c.cmd_socket;
+    let sounds = SoundLibrary::new(c.sounds_dir.or_else(SoundLibrary::default_dir).unwrap_or_default());
+    let calendar = match WorkCalendar::load(c.holidays.as_deref(), &c.workdays) {
+        Ok(cal) => cal,
+        Err(e) => {
+            println!("{e}");
+            exit(1);
+        }
+    };
     match c.subcommand {
-        SubCommand::Add { name, soundfile, timeparts } => {
-            ensure_soundfile(&soundfile);
-            let tps_raw: String = timeparts.join(" ");
-            let (_, tps) = TimePart::parse_line(&tps_raw).map_err(|_| "input error" )?;
-            let end_t = timeparts_to_unixmoment(dt.clone(), tps.as_slice())?;
+        SubCommand::Add { name, soundfile, sound_order, strict, dst, repeat, daily, workdays_only, loop_sound, notes, tags, dry_run, json, timeparts } => {
+            // in json mode errors are printed as json too, so editors can show them
+            let fail = |e: String| -> ! {
+                if json {
+                    println!("{}", serde_json::json!({ "error": e }));
+                } else {
+                    println!("{e}");
+                }
+                exit(1);
+            };
+            let soundfile = match soundfile {
+                Some(ref sound) if ! dry_run => Some(ensure_soundfile(sound, &sounds)),
+                _ => soundfile,
+            };
+            if workdays_only && repeat.is_none() && ! daily {
+                fail(String::from("--workdays-only needs --repeat or --daily"));
+            }
+            let schedule = match repeat.map(|r| Schedule::parse(&r).map_err(|_| r)) {
+                Some(Ok(s)) => Some(s),
+                Some(Err(r)) => fail(format!("Not a cron or OnCalendar schedule: {r}")),
+                None => None,
+            };
+            let expr: String = timeparts.join(" ");
+            let tokens = match parse_expr_tokens(&expr) {
+                Ok(tokens) => tokens,
+                Err(_) if timeparts.is_empty() => Vec::new(),
+                Err(e) => fail(e.to_string()),
+            };
+            let resolution = if strict { Resolution::Strict } else { Resolution::NextOccurrence };
+            let options = ResolveOptions { resolution, dst, calendar: Some(&calendar), location: c.location };
+            let resolved = match schedule {
+                // without a time, a recurring alarm starts at its first fire time
+                Some(ref s) if timeparts.is_empty() && workdays_only => s.next_working_after(now, tz.as_ref(), &calendar)
+                    .map(|moment| Resolved { moment, wall_time: WallTime::Normal })
+                    .ok_or(String::from("Schedule never fires on a working day")),
+                Some(ref s) if timeparts.is_empty() => s.next_after(now, tz.as_ref())
+                    .map(|moment| Resolved { moment, wall_time: WallTime::Normal })
+                    .ok_or(String::from("Schedule never fires")),
+                _ if daily => Alarm::next_daily(&expr, now, tz.as_ref(), options, workdays_only)
+                    .map(|moment| Resolved { moment, wall_time: WallTime::Normal })
+                    .map_err(|e| e.to_string()),
+                _ => resolve_with(&expr, now, tz.as_ref(), options).map_err(|e| e.to_string()),
+            };
+            let Resolved { moment: end_t, wall_time } = resolved.unwrap_or_else(|e| fail(e));
+            let which = format!("{dst:?}").to_lowercase();
+            let dst_warning = match wall_time {
+                WallTime::Normal => None,
+                WallTime::Skipped => Some(format!("that time is skipped by a DST change, using the {which} valid time")),
+                WallTime::Ambiguous => Some(format!("that time occurs twice due to a DST change, using the {which} one")),
+            };
             let name = name.unwrap_or(String::from("Anon"));
-            let alarm = AlarmSpec::new(name, soundfile, end_t);
-            let cmd = AppCommand::Add(alarm);
-            send_command(cmd_socket, cmd)?;
-            // let new_dt = um.as_datetime(tz.as_ref()).unwrap();
-            // println!("{}", humanize_datetime_full(new_dt));
-            // println!("name is {name:?}");
-            // println!("soundfile is {soundfile:?}");
+            let time = end_t.as_datetime(tz.as_ref()).map(humanize_datetime_full).unwrap_or_default();
+            let remaining = end_t.seconds() - now.seconds();
+            if json {
+                let preview = Preview {
+                    name: &name,
+                    expr: &expr,
+                    tokens: tokens.iter().map(|(text, tp)| PreviewToken { text, meaning: tp.to_string() }).collect(),
+                    unix_time: end_t.seconds(),
+                    time,
+                    remaining_seconds: remaining,
+                    remaining: humanize_seconds(remaining),
+                    dst_warning,
+                    repeats: schedule.as_ref().map(Schedule::to_string).or(daily.then(|| String::from("daily"))),
+                    workdays_only,
+                };
+                println!("{}", serde_json::to_string(&preview)?);
+                return Ok(());
+            }
+            if dry_run {
+                for (text, tp) in tokens.iter() {
+                    println!("{text:>16}  {tp}");
+                }
+            }
+            if let Some(w) = dst_warning {
+                println!("Warning: {w}");
+            }
+            println!("{name}: {time}");
+            if dry_run {
+                println!("in {}", humanize_seconds(remaining));
+            }
+            let mut alarm = AlarmSpec::new(name, soundfile, end_t);
+            if daily {
+                println!("repeats daily");
+                alarm = alarm.repeat_daily(expr.clone());
+            }
+            if let Some(s) = schedule {
+                println!("repeats: {s}");
+                alarm = alarm.with_recurrence(s);
+            }
+            if workdays_only {
+                println!("skipping days off");
+                alarm = alarm.workdays_only();
+            }
+            if loop_sound {
+                alarm = alarm.loop_sound();
+            }
+            alarm = alarm.with_sound_selection(sound_order);
+            if let Some(notes) = notes {
+                alarm = alarm.with_notes(notes);
+            }
+            if ! tags.is_empty() {
+                alarm = alarm.with_tags(tags);
+            }
+            if dry_run { return Ok(()); }
+            let cmd = AppCommand::Add(Box::new(alarm));
+            send_command(socket_path(cmd_socket)?, cmd)?;
+        },
+        SubCommand::Ack { id: None } => {
+            send_command(socket_path(cmd_socket)?, AppCommand::Acknowledge)?;
         },
-        SubCommand::Ack => {
-            send_command(cmd_socket, AppCommand::Acknowledge)?;
+        SubCommand::Ack { id: Some(id) } => {
+            send_command(socket_path(cmd_socket)?, AppCommand::AcknowledgeAlarm(UnixMoment::from_nanos(id)))?;
+        },
+        SubCommand::Snooze { id, minutes } => {
+            let socket = socket_path(cmd_socket)?;
+            let ids = match id {
+                Some(id) => vec![UnixMoment::from_nanos(id)],
+                None => query::<Vec<PendingAlarm>>(&socket, AppCommand::List)?.into_iter()
+                    .filter(|a| a.ringing)
+                    .map(|a| a.id)
+                    .collect(),
+            };
+            let wait = Duration::from_secs(minutes * 60);
+            send_commands(socket, ids.into_iter().map(|id| AppCommand::Snooze(id, wait)).collect())?;
         },
         SubCommand::List => {
-            todo!()
+            let mut pending: Vec<PendingAlarm> = query(socket_path(cmd_socket)?, AppCommand::List)?;
+            pending.sort_by_key(|a| (! a.ringing, a.end_t));
+            for a in pending {
+                let time = a.end_t.as_datetime(tz.as_ref()).map(humanize_datetime_full).unwrap_or_default();
+                let state = if a.ringing { "ringing" } else { "" };
+                let mut line = format!("{:>19} {state:7} {time}  {}", a.id.as_nanos(), a.record.name);
+                if let Some(r) = a.record.repeat {
+                    line += &format!(", repeats {r}");
+                }
+                if a.record.daily {
+                    line += &format!(", daily at {}", a.record.time);
+                }
+                if a.managed {
+                    line += " (calendar)";
+                }
+                if ! a.record.tags.is_empty() {
+                    line += &format!(" [{}]", a.record.tags.join(", "));
+                }
+                println!("{line}");
+            }
+        },
+        SubCommand::Export { format } => {
+            let mut pending: Vec<PendingAlarm> = query(socket_path(cmd_socket)?, AppCommand::List)?;
+            pending.retain(|a| ! a.ringing);
+            pending.sort_by_key(|a| a.end_t);
+            match format {
+                ExportFormat::Ics => print!("{}", export_ics(&pending, now)),
+                ExportFormat::Json => for a in pending.iter() {
+                    println!("{}", serde_json::to_string(&a.record)?);
+                },
+                ExportFormat::Csv => {
+                    println!("{RECORD_CSV_HEADER}");
+                    for a in pending.iter() {
+                        println!("{}", a.record.to_csv());
+                    }
+                },
+            }
         },
         SubCommand::SwitchDisplay => {
-            send_command(cmd_socket, AppCommand::SwitchDisplay)?;
+            send_command(socket_path(cmd_socket)?, AppCommand::SwitchDisplay)?;
+        }
+        SubCommand::Chime { state } => {
+            send_command(socket_path(cmd_socket)?, AppCommand::Chimes(matches!(state, Switch::On)))?;
+        }
+        SubCommand::Dnd { state } => {
+            send_command(socket_path(cmd_socket)?, AppCommand::DoNotDisturb(matches!(state, Switch::On)))?;
+        }
+        SubCommand::NextFires { schedule, count } => {
+            let s = ensure_schedule(&schedule);
+            for t in s.next_n(now, tz.as_ref(), count) {
+                if let Some(dt) = t.as_datetime(tz.as_ref()) {
+                    println!("{}", humanize_datetime_full(dt));
+                }
+            }
+        }
+        SubCommand::Import { file, daemon: true, .. } if is_record_file(&file) => {
+            // the daemon only reads calendars, records go through the batch below
+            let mut cmd = <Config as clap::CommandFactory>::command();
+            cmd.error(clap::error::ErrorKind::ArgumentConflict, "--daemon only imports .ics files, leave it out for records and stdin").exit();
+        },
+        SubCommand::Import { file, daemon: true, .. } => {
+            let path = std::fs::canonicalize(&file)?;
+            send_command(socket_path(cmd_socket)?, AppCommand::ImportIcs(path))?;
+        },
+        SubCommand::Import { file, dry_run, .. } if is_record_file(&file) => {
+            let text = if file == Path::new("-") {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                std::fs::read_to_string(&file)?
+            };
+            let options = ResolveOptions { calendar: Some(&calendar), location: c.location, ..Default::default() };
+            // check every record the way the daemon will before sending any
+            let mut specs = Vec::new();
+            let mut failed = false;
+            for (line, record) in parse_records(&text) {
+                let checked = record.and_then(AlarmRecord::into_spec).map_err(|e| e.to_string())
+                    .and_then(|spec| match spec.clone().into_alarm(now, tz.as_ref(), options) {
+                        Ok(_) => Ok(spec),
+                        Err(e) => Err(e.to_string()),
+                    });
+                match checked {
+                    Ok(spec) => specs.push((line, spec)),
+                    Err(e) => {
+                        println!("line {line}: {e}");
+                        failed = true;
+                    },
+                }
+            }
+            if failed {
+                println!("Nothing was imported");
+                exit(1);
+            }
+            for (_, spec) in specs.iter() {
+                println!("{spec}");
+            }
+            if dry_run || specs.is_empty() { return Ok(()); }
+            let batch = specs.iter().map(|(_, s)| AppCommand::Add(Box::new(s.clone()))).collect();
+            let outcomes: Vec<CommandOutcome> = query(socket_path(cmd_socket)?, AppCommand::Batch(batch))?;
+            let mut failed = false;
+            for ((line, _), outcome) in specs.iter().zip(outcomes) {
+                if let CommandOutcome::Failed(e) = outcome {
+                    println!("line {line}: {e}");
+                    failed = true;
+                }
+            }
+            if failed {
+                println!("Nothing was imported");
+                exit(1);
+            }
+        },
+        SubCommand::Import { file, dry_run, .. } => {
+            let text = std::fs::read_to_string(&file)?;
+            let import = match import_ics(&text, now, tz.as_ref()) {
+                Ok(import) => import,
+                Err(e) => {
+                    println!("{e}");
+                    exit(1);
+                }
+            };
+            for w in import.warnings.iter() {
+                println!("Warning: {w}");
+            }
+            for spec in import.specs.iter() {
+                println!("{spec}");
+            }
+            if dry_run { return Ok(()); }
+            let cmds = import.specs.into_iter().map(|s| AppCommand::Add(Box::new(s))).collect();
+            send_commands(socket_path(cmd_socket)?, cmds)?;
+        },
+        SubCommand::Sounds(SoundsCommand::List) => {
+            for (name, path) in sounds.list() {
+                println!("{name:16} {}", path.display());
+            }
+        },
+        SubCommand::Sounds(SoundsCommand::Play { name, player, volume }) => {
+            let Some(file) = sounds.lookup(&name) else {
+                println!("No sound named {} in {:?}", name.display(), sounds.dir);
+                exit(1);
+            };
+            let path = std::env::var_os("PATH");
+            let player = match player {
+                Some(p) => PlayerTemplate::from_config(&p),
+                None => PlayerTemplate::probe(path.as_deref()),
+            };
+            let (program, template) = match player.and_then(|t| t.locate(path.as_deref()).map(|p| (p, t))) {
+                Ok(p) => p,
+                Err(e) => {
+                    println!("{e}");
+                    exit(1);
+                }
+            };
+            let status = std::process::Command::new(program)
+                .args(template.args_for(&file, volume, 1, None))
+                .status()?;
+            if ! status.success() {
+                println!("{} failed with {status}", template.program);
+                exit(1);
+            }
+        },
+        SubCommand::Calendar(CalendarCommand::Check { date }) => {
+            let expr = date.join(" ");
+            let date = match resolve_day(&expr, now, tz.as_ref(), ResolveOptions { calendar: Some(&calendar), location: c.location, ..Default::default() }) {
+                Ok(date) => date,
+                Err(e) => {
+                    println!("{e}");
+                    exit(1);
+                }
+            };
+            let (y, m, d) = date;
+            match calendar.day_kind(date) {
+                DayKind::Working => println!("{y}-{m:02}-{d:02} is a working day"),
+                DayKind::DayOff => println!("{y}-{m:02}-{d:02} is not a working day"),
+                DayKind::Holiday(desc) if desc.is_empty() => println!("{y}-{m:02}-{d:02} is a holiday"),
+                DayKind::Holiday(desc) => println!("{y}-{m:02}-{d:02} is a holiday: {desc}"),
+            }
+            if let Ok((y, m, d)) = calendar.add_workdays(date, 1) {
+                println!("next working day: {y}-{m:02}-{d:02}");
+            }
         }
     }
     Ok(())
 }
 
-fn next_day(dt: DateTime) -> Result<DateTime, tz::error::TzError> {
-    let us = dt.unix_time() + 86400;
-    let ltt = dt.local_time_type().clone();
-    Ok(DateTime::from_timespec_and_local(us, 0, ltt)?)
+/// files of alarm records rather than iCalendar
+fn is_record_file(file: &Path) -> bool {
+    file == Path::new("-") || matches!(file.extension(), Some(e) if e == "json" || e == "jsonl" || e == "csv")
 }
 
-fn find_weekday(starting_dt: DateTime, weekday: u8) -> Result<DateTime, tz::error::TzError> {
-    let mut counter = 7;
-    let mut dt = next_day(starting_dt)?;
-    while counter > 0 {
-        if dt.week_day() == weekday { break; }
-        dt = next_day(dt)?;
-        counter -= 1;
+/// Sound names must be in the library, paths are made absolute for the daemon
+fn ensure_soundfile(sound: &Path, sounds: &SoundLibrary) -> PathBuf {
+    if sound.components().count() == 1 && ! sound.exists() {
+        if sounds.lookup(sound).is_some() { return sound.to_owned(); }
+        println!("No sound named {} in {:?}", sound.display(), sounds.dir);
+        exit(1);
     }
-    if counter == 0 {
-        let e: std::io::Error = std::io::ErrorKind::InvalidInput.into();
-        return Err(tz::error::TzError::from(e));
+    // a glob's directory must exist
+    let absolute = match sound.file_name() {
+        Some(pattern) if is_glob(sound) => sound.parent()
+            .map(|d| if d.as_os_str().is_empty() { Path::new(".") } else { d })
+            .and_then(|d| std::fs::canonicalize(d).ok())
+            .map(|d| d.join(pattern)),
+        _ => std::fs::canonicalize(sound).ok(),
+    };
+    match absolute {
+        Some(p) if ! sound_files(&p).is_empty() => p,
+        _ => {
+            println!("Sound file is not valid");
+            exit(1);
+        }
     }
-    Ok(dt)
 }
 
-fn  timeparts_to_unixmoment(mut current_dt: DateTime, tps: &[TimePart]) -> Result<UnixMoment, tz::error::TzError> {
-    let tz = tz::TimeZone::local().unwrap();
-    // find starting day
-    for tp in tps {
-        match tp {
-            TimePart::Tomorrow => current_dt = next_day(current_dt)?,
-            TimePart::WeekDay(n) => current_dt = find_weekday(current_dt, *n)?,
-            _ => continue,
-        }
-    }
-    let mut year = current_dt.year();
-    let mut month = current_dt.month();
-    let mut day = current_dt.month_day();
-    let mut hour = current_dt.hour();
-    let mut minute = current_dt.minute();
-    let mut second = current_dt.second();
-    let mut interval_seconds = 0i64;
-
-    for tp in tps {
-        match tp {
-            TimePart::HM(h, m) => {
-                hour = *h;
-                minute = *m;
-            },
-            TimePart::MD(m, d) => {
-                month = *m;
-                day = *d;
-            },
-            TimePart::Month(m) => month = *m,
-            TimePart::Year(y) => year = *y,
-            TimePart::YMD(y, m, d) => {
-                year = *y;
-                month = *m;
-                day = *d;
-            },
-            TimePart::Hours(h) => interval_seconds += (h * 3600),
-            TimePart::Minutes(m) => interval_seconds += (m * 60),
-            TimePart::Seconds(s) => interval_seconds += s,
-            _ => continue,
+fn ensure_schedule(s: &str) -> Schedule {
+    match Schedule::parse(s) {
+        Ok(s) => s,
+        Err(e) => {
+            println!("Not a cron or OnCalendar schedule: {s}");
+            exit(1);
         }
     }
-    let ltt = current_dt.local_time_type();
-    let new_dt = DateTime::new(year, month, day, hour, minute, second, 0, *ltt)?;
-    Ok(UnixMoment::new(new_dt.unix_time() + interval_seconds))
 }
 
+fn send_command(path: impl AsRef<Path>, cmd: AppCommand) -> Anything<()> {
+    send_commands(path, vec![cmd])
+}
 
-fn verify_soundfile(p: impl AsRef<Path>) -> bool {
-    p.as_ref().exists() && p.as_ref().is_file()
+/// sends the commands over one connection, one json line each
+fn send_commands(path: impl AsRef<Path>, cmds: Vec<AppCommand>) -> Anything<()> {
+    if ! path.as_ref().exists() {
+        println!("Command socket {:?} does not exist", path.as_ref());
+        exit(1);
+    }
+    let mut socket = UnixStream::connect(path)?;
+    for cmd in cmds {
+        serde_json::to_writer(&mut socket, &cmd)?;
+        socket.write_all(b"\n")?;
+    }
+    Ok(())
 }
 
-fn ensure_soundfile(p: &Option<impl AsRef<Path>>) {
-    // let sf = soundfile.as_ref().map(verify_soundfile);
-    if let Some(path) = p.as_ref() {
-        if verify_soundfile(path) { return }
-    } else {
-        return
+/// sends a query and reads the daemon's one line reply
+fn query<T: serde::de::DeserializeOwned>(path: impl AsRef<Path>, cmd: AppCommand) -> Anything<T> {
+    if ! path.as_ref().exists() {
+        println!("Command socket {:?} does not exist", path.as_ref());
+        exit(1);
     }
-    println!("Sound file is not valid");
-    exit(1);
+    let mut socket = UnixStream::connect(path)?;
+    serde_json::to_writer(&mut socket, &cmd)?;
+    socket.write_all(b"\n")?;
+    let mut reply = String::new();
+    BufReader::new(socket).read_line(&mut reply)?;
+    Ok(serde_json::from_str(&reply)?)
 }
 
-fn send_command(path: impl AsRef<Path>, cmd: AppCommand) -> Anything<()> {
-    let socket = UnixStream::connect(path)?;
-    serde_json::to_writer(socket, &cmd)?;
-    Ok(())
+/// path of the daemon's command socket, only needed for commands that talk to it
+fn socket_path(mut config_path: Option<PathBuf>) -> Anything<PathBuf> {
+    make_socket_path(&mut config_path, "clockd.cmd")?;
+    Ok(config_path.expect("Could not determine socket path"))
 }
 
 fn make_socket_path(config_path: &mut Option<PathBuf>, default_name: &str) -> Anything<()> {
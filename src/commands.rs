@@ -3,24 +3,58 @@ use async_std::{
     os::unix::net::{UnixStream, UnixListener},
     io::{BufReader, BufRead},
     io::prelude::BufReadExt,
+    io::WriteExt,
 };
 use serde::Deserialize;
 
-/// Protocol is a json representation of AppCommand, followed by a newline
-async fn handle_command_stream(stream: UnixStream, cmd_tx: Sender<AppCommand>) {
+/// Protocol is a json representation of AppCommand, followed by a newline.
+/// Queries are answered with one json line on the same stream.
+async fn handle_command_stream(
+    stream: UnixStream,
+    cmd_tx: Sender<AppCommand>,
+    batch_tx: Sender<(Vec<AppCommand>, Sender<Vec<CommandOutcome>>)>,
+    alarm_list: Arc<RwLock<AlarmList>>,
+    ) {
+    let mut writer = stream.clone();
     let mut b = BufReader::new(stream);
     let mut buf = String::new();
     while let Ok(bytes_read) = b.read_line(&mut buf).await {
         // EOF
         if bytes_read == 0 { break; }
         // try to deserialize AppCommand from json
-        if let Ok(cmd) = serde_json::from_str::<AppCommand>(&buf) {
-            cmd_tx.send(cmd).await;
+        match serde_json::from_str::<AppCommand>(&buf) {
+            Ok(AppCommand::List) => {
+                let pending: Vec<PendingAlarm> = {
+                    let x = alarm_list.read().unwrap();
+                    x.alarming()
+                        .map(|a| PendingAlarm { ringing: true, ..PendingAlarm::from_alarm(a) })
+                        .chain(x.pending().map(PendingAlarm::from_alarm))
+                        .collect()
+                };
+                reply(&mut writer, &pending).await;
+            },
+            Ok(AppCommand::Batch(cmds)) => {
+                let (outcome_tx, outcome_rx) = channel::bounded(1);
+                batch_tx.send((cmds, outcome_tx)).await;
+                if let Ok(outcomes) = outcome_rx.recv().await {
+                    reply(&mut writer, &outcomes).await;
+                }
+            },
+            Ok(cmd) => { cmd_tx.send(cmd).await; },
+            Err(_) => {},
         }
         buf.clear(); 
     }
 }
 
+async fn reply(writer: &mut UnixStream, value: &impl serde::Serialize) {
+    let mut line = serde_json::to_string(value).unwrap();
+    line.push('\n');
+    if let Err(e) = writer.write_all(line.as_bytes()).await {
+        warn!("could not reply on the command socket: {e}");
+    }
+}
+
 async fn prep_command_socket(path: &Path) {
     if path.exists().await {
         std::fs::remove_file(path)
@@ -28,12 +62,17 @@ async fn prep_command_socket(path: &Path) {
     }
 }
 
-pub async fn start_command_socket(path: impl AsRef<Path>, cmd_tx: Sender<AppCommand>) {
+pub async fn start_command_socket(
+    path: impl AsRef<Path>,
+    cmd_tx: Sender<AppCommand>,
+    batch_tx: Sender<(Vec<AppCommand>, Sender<Vec<CommandOutcome>>)>,
+    alarm_list: Arc<RwLock<AlarmList>>,
+    ) {
     prep_command_socket(path.as_ref()).await;
     let s = UnixListener::bind(path).await.unwrap();
     loop {
         if let Ok((stream, _)) = s.accept().await {
-            spawn(handle_command_stream(stream, cmd_tx.clone()));
+            spawn(handle_command_stream(stream, cmd_tx.clone(), batch_tx.clone(), alarm_list.clone()));
         };
     }
     unreachable!()
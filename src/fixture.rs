@@ -0,0 +1,11 @@
+//! A fixed clock and time zone for the tests of both the library and the
+//! daemon
+use crate::*;
+
+/// Wed 2024-03-06 12:00 EST
+pub const NOW: i64 = 1709744400;
+
+/// US eastern time, with daylight saving time from march to november
+pub fn eastern() -> tz::TimeZone {
+    tz::TimeZone::from_posix_tz("EST5EDT,M3.2.0,M11.1.0").unwrap()
+}
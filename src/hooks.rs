@@ -0,0 +1,302 @@
+use crate::*;
+use async_std::process::{Command, Stdio};
+use async_std::io::ReadExt;
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+/// how long to keep reading a hook's output after it exits, in case it left
+/// something running that holds on to it
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// Alarm events that run hooks, named like the hook scripts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Added,
+    Warned,
+    Ring,
+    Snoozed,
+    Acknowledged,
+}
+
+impl HookEvent {
+    const ALL: [HookEvent; 5] = [HookEvent::Added, HookEvent::Warned, HookEvent::Ring, HookEvent::Snoozed, HookEvent::Acknowledged];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::Added => "added",
+            HookEvent::Warned => "warned",
+            HookEvent::Ring => "ring",
+            HookEvent::Snoozed => "snoozed",
+            HookEvent::Acknowledged => "acknowledged",
+        }
+    }
+}
+
+/// A shell command to run on an event, from "ring=notify-send hello"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookSpec {
+    pub event: HookEvent,
+    pub command: String,
+}
+
+#[derive(Debug)]
+pub struct HookSpecError(String);
+impl std::error::Error for HookSpecError {}
+impl std::fmt::Display for HookSpecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let events: Vec<&str> = HookEvent::ALL.iter().map(|e| e.name()).collect();
+        write!(f, "Hooks should look like EVENT=COMMAND with EVENT one of {}, got {}", events.join(", "), self.0)
+    }
+}
+
+impl std::str::FromStr for HookSpec {
+    type Err = HookSpecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || HookSpecError(s.to_owned());
+        let (event, command) = s.split_once('=').ok_or_else(err)?;
+        let event = HookEvent::ALL.into_iter().find(|e| e.name() == event.trim()).ok_or_else(err)?;
+        if command.trim().is_empty() {
+            return Err(err());
+        }
+        Ok(Self { event, command: command.to_owned() })
+    }
+}
+
+/// Something to run: an executable from the hooks directory or a command
+/// line for the shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hook {
+    Script(PathBuf),
+    Shell(String),
+}
+
+impl std::fmt::Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Hook::Script(path) => write!(f, "{}", path.display()),
+            Hook::Shell(command) => write!(f, "{command:?}"),
+        }
+    }
+}
+
+/// What a hook printed and how it ended, None if it was killed for taking
+/// too long
+#[derive(Debug)]
+pub struct HookOutcome {
+    pub status: Option<ExitStatus>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl HookOutcome {
+    fn log(&self, hook: &Hook, event: HookEvent, timeout: Duration) {
+        for line in self.stdout.lines().filter(|l| ! l.is_empty()) {
+            info!("hook {hook}: {line}");
+        }
+        for line in self.stderr.lines().filter(|l| ! l.is_empty()) {
+            warn!("hook {hook}: {line}");
+        }
+        match self.status {
+            Some(s) if s.success() => info!("hook {hook} on {}: {s}", event.name()),
+            Some(s) => warn!("hook {hook} on {}: {s}", event.name()),
+            None => warn!("hook {hook} on {}: killed after {}s", event.name(), timeout.as_secs()),
+        }
+    }
+}
+
+/// The environment a hook runs in, describing the alarm
+pub fn hook_env(event: HookEvent, alarm: &Alarm) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+        ("CLOCKD_EVENT", event.name().to_owned()),
+        ("CLOCKD_ALARM_NAME", alarm.name.clone()),
+        ("CLOCKD_ALARM_ID", alarm.creation_t.as_nanos().to_string()),
+        ("CLOCKD_ALARM_END", alarm.end_t.seconds().to_string()),
+        ("CLOCKD_ALARM_NOTES", alarm.notes.clone().unwrap_or_default()),
+        ("CLOCKD_ALARM_TAGS", alarm.tags.join(" ")),
+    ];
+    if let Some(ref sound) = alarm.soundfile {
+        env.push(("CLOCKD_ALARM_SOUND", sound.to_string_lossy().into_owned()));
+    }
+    env
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata().map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+/// Where hooks come from and how long they may run
+pub struct Hooks {
+    dir: Option<PathBuf>,
+    commands: Vec<HookSpec>,
+    timeout: Duration,
+}
+
+impl Hooks {
+    pub fn new(dir: Option<PathBuf>, commands: Vec<HookSpec>, timeout: Duration) -> Self {
+        Self { dir, commands, timeout }
+    }
+
+    /// $XDG_CONFIG_HOME/clockd/hooks
+    pub fn default_dir() -> Option<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|d| ! d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+        Some(config.join("clockd/hooks"))
+    }
+
+    /// The hooks for an event: `<dir>/<event>`, the executables in
+    /// `<dir>/<event>.d` by name, then the configured commands. The
+    /// directory is read every time, so hooks can change while clockd runs.
+    pub fn for_event(&self, event: HookEvent) -> Vec<Hook> {
+        let mut hooks = Vec::new();
+        if let Some(ref dir) = self.dir {
+            let script = dir.join(event.name());
+            if is_executable(&script) {
+                hooks.push(Hook::Script(script));
+            }
+            if let Ok(entries) = std::fs::read_dir(dir.join(format!("{}.d", event.name()))) {
+                let mut scripts: Vec<PathBuf> = entries
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .filter(|p| is_executable(p))
+                    .collect();
+                scripts.sort();
+                hooks.extend(scripts.into_iter().map(Hook::Script));
+            }
+        }
+        hooks.extend(self.commands.iter()
+            .filter(|c| c.event == event)
+            .map(|c| Hook::Shell(c.command.clone())));
+        hooks
+    }
+
+    /// Runs the hook, killing it once it has run for longer than the timeout
+    pub async fn run(&self, hook: &Hook, env: &[(&'static str, String)]) -> std::io::Result<HookOutcome> {
+        let mut command = match hook {
+            Hook::Script(path) => Command::new(path),
+            Hook::Shell(line) => {
+                let mut c = Command::new("sh");
+                c.arg("-c").arg(line);
+                c
+            },
+        };
+        let mut child = command
+            .envs(env.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().map(|mut out| spawn(async move {
+            let mut s = String::new();
+            out.read_to_string(&mut s).await.ok();
+            s
+        }));
+        let stderr = child.stderr.take().map(|mut err| spawn(async move {
+            let mut s = String::new();
+            err.read_to_string(&mut s).await.ok();
+            s
+        }));
+        let status = match async_std::future::timeout(self.timeout, child.status()).await {
+            Ok(status) => Some(status?),
+            Err(_) => {
+                child.kill().ok();
+                child.status().await.ok();
+                None
+            },
+        };
+        let read = |task: Option<async_std::task::JoinHandle<String>>| async move {
+            match task {
+                Some(t) => async_std::future::timeout(OUTPUT_GRACE, t).await.unwrap_or_default(),
+                None => String::new(),
+            }
+        };
+        Ok(HookOutcome { status, stdout: read(stdout).await, stderr: read(stderr).await })
+    }
+}
+
+/// Runs the hooks of alarm events in the background. Acknowledging and
+/// snoozing only name the alarm, so the ringing ones are kept to describe.
+pub async fn run_hooks(hooks: Hooks, mut event_rx: broadcast::Receiver<AppEvent>) {
+    let hooks = Arc::new(hooks);
+    let mut ringing: BTreeMap<UnixMoment, Alarm> = BTreeMap::new();
+    while let Some(ev) = event_rx.next().await {
+        let fired: Vec<(HookEvent, Alarm)> = match ev {
+            AppEvent::Added(a) => vec![(HookEvent::Added, *a)],
+            AppEvent::Warn(a) => vec![(HookEvent::Warned, *a)],
+            AppEvent::Ring(a) => {
+                ringing.insert(a.creation_t, (*a).clone());
+                vec![(HookEvent::Ring, *a)]
+            },
+            AppEvent::Snooze(t) => ringing.remove(&t).map(|a| (HookEvent::Snoozed, a)).into_iter().collect(),
+            AppEvent::AckAlarm(t) => ringing.remove(&t).map(|a| (HookEvent::Acknowledged, a)).into_iter().collect(),
+            AppEvent::Ack => std::mem::take(&mut ringing).into_values().map(|a| (HookEvent::Acknowledged, a)).collect(),
+            _ => continue,
+        };
+        for (event, alarm) in fired {
+            for hook in hooks.for_event(event) {
+                let hooks = hooks.clone();
+                let env = hook_env(event, &alarm);
+                spawn(async move {
+                    match hooks.run(&hook, &env).await {
+                        Ok(outcome) => outcome.log(&hook, event, hooks.timeout),
+                        Err(e) => error!("hook {hook} on {}: {e}", event.name()),
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+    use crate::fixture::*;
+
+    #[test]
+    fn runs_hooks() {
+        let dir = std::env::temp_dir().join(format!("clockd-hooks-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("ring.d")).unwrap();
+        let write_script = |path: PathBuf, body: &str| {
+            std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        write_script(dir.join("ring"), "echo \"$CLOCKD_EVENT $CLOCKD_ALARM_NAME $CLOCKD_ALARM_END [$CLOCKD_ALARM_TAGS] $CLOCKD_ALARM_NOTES\"; echo oops >&2; exit 3");
+        write_script(dir.join("ring.d/slow"), "sleep 5");
+        std::fs::write(dir.join("ring.d/README"), "not a hook").unwrap();
+        let spec: HookSpec = "ring=echo $CLOCKD_ALARM_ID".parse().unwrap();
+        assert!("rang=true".parse::<HookSpec>().is_err());
+        let hooks = Hooks::new(Some(dir.clone()), vec![spec], Duration::from_millis(300));
+
+        let found = hooks.for_event(HookEvent::Ring);
+        assert_eq!(vec![
+            Hook::Script(dir.join("ring")),
+            Hook::Script(dir.join("ring.d/slow")),
+            Hook::Shell(String::from("echo $CLOCKD_ALARM_ID")),
+        ], found);
+        assert!(hooks.for_event(HookEvent::Added).is_empty());
+
+        let mut tea = Alarm::new_from_unixmoment("Tea", None, UnixMoment::new(NOW));
+        tea.notes = Some(String::from("green"));
+        tea.tags = vec![String::from("kitchen"), String::from("home")];
+        let env = hook_env(HookEvent::Ring, &tea);
+        async_std::task::block_on(async {
+            let ring = hooks.run(&found[0], &env).await.unwrap();
+            assert_eq!(format!("ring Tea {NOW} [kitchen home] green\n"), ring.stdout);
+            assert_eq!("oops\n", ring.stderr);
+            assert_eq!(Some(3), ring.status.and_then(|s| s.code()));
+
+            let started = Instant::now();
+            let slow = hooks.run(&found[1], &env).await.unwrap();
+            assert!(slow.status.is_none());
+            assert!(started.elapsed() < Duration::from_secs(3));
+
+            let shell = hooks.run(&found[2], &env).await.unwrap();
+            assert_eq!(format!("{}\n", tea.creation_t.as_nanos()), shell.stdout);
+            assert!(shell.status.unwrap().success());
+        });
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,585 @@
+use crate::*;
+use tz::{TimeZone, TimeZoneRef};
+
+/// One content line, e.g. "DTSTART;TZID=Europe/Berlin:20240306T090000"
+#[derive(Debug, Clone)]
+pub(crate) struct Property {
+    pub(crate) name: String,
+    params: Vec<(String, String)>,
+    pub(crate) value: String,
+}
+
+impl Property {
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        // the value starts at the first colon that is not inside a quoted parameter
+        let mut quoted = false;
+        let split = line.char_indices().find(|(_, c)| {
+            if *c == '"' { quoted = ! quoted; }
+            *c == ':' && ! quoted
+        })?.0;
+        let (head, value) = (&line[..split], &line[split + 1..]);
+        let mut parts = head.split(';');
+        let name = parts.next()?.to_uppercase();
+        let params = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.to_uppercase(), v.trim_matches('"').to_owned()))
+            .collect();
+        Some(Self { name, params, value: value.to_owned() })
+    }
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Joins folded lines, which continue with a leading space or tab
+pub(crate) fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_owned()),
+        }
+    }
+    lines
+}
+
+/// Undoes `escape` in one pass, so that `\\n` is a backslash and an n
+pub(crate) fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[derive(Debug)]
+pub enum IcalError {
+    /// BEGIN and END lines do not match
+    Structure(String),
+    BadDate(String),
+    BadDuration(String),
+    UnknownZone(String),
+    MissingStart,
+}
+impl std::error::Error for IcalError {}
+impl std::fmt::Display for IcalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IcalError::Structure(s) => write!(f, "Malformed calendar: {s}"),
+            IcalError::BadDate(s) => write!(f, "Bad date: {s}"),
+            IcalError::BadDuration(s) => write!(f, "Bad duration: {s}"),
+            IcalError::UnknownZone(s) => write!(f, "Unknown time zone: {s}"),
+            IcalError::MissingStart => write!(f, "Event has no DTSTART"),
+        }
+    }
+}
+
+/// When a VALARM goes off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// seconds from the start, or from the end with RELATED=END
+    Relative { seconds: i64, from_end: bool },
+    Absolute(UnixMoment),
+}
+
+/// The parts of a VEVENT that matter for alarms
+#[derive(Debug, Clone)]
+pub struct IcalEvent {
+    pub uid: String,
+    pub summary: String,
+    pub description: Option<String>,
+    pub categories: Vec<String>,
+    pub start: UnixMoment,
+    pub end: Option<UnixMoment>,
+    pub rrule: Option<String>,
+    /// empty if the event has no VALARM
+    pub triggers: Vec<Trigger>,
+}
+
+/// Alarms read from a calendar, plus what could not be carried over
+#[derive(Debug, Default)]
+pub struct IcsImport {
+    pub specs: Vec<AlarmSpec>,
+    pub warnings: Vec<String>,
+}
+
+/// "20240306", "20240306T090000" or "20240306T090000Z". Floating times are
+/// read in `tz`, unless the property has a TZID.
+fn parse_datetime(p: &Property, tz: TimeZoneRef) -> Result<UnixMoment, IcalError> {
+    let bad = || IcalError::BadDate(p.value.clone());
+    let v = p.value.trim();
+    let num = |r: std::ops::Range<usize>| v.get(r).and_then(|s| s.parse::<u8>().ok()).ok_or_else(bad);
+    let year: i32 = v.get(0..4).and_then(|s| s.parse().ok()).ok_or_else(bad)?;
+    let date = (year, num(4..6)?, num(6..8)?);
+    let time = match v.get(8..9) {
+        Some("T") => (num(9..11)?, num(11..13)?, num(13..15)?),
+        _ => (0, 0, 0),
+    };
+    if v.ends_with('Z') {
+        let dt = tz::UtcDateTime::new(date.0, date.1, date.2, time.0, time.1, time.2, 0).map_err(|_| bad())?;
+        return Ok(UnixMoment::new(dt.unix_time()));
+    }
+    let zone = match p.param("TZID") {
+        Some(name) => Some(TimeZone::from_posix_tz(name).map_err(|_| IcalError::UnknownZone(name.to_owned()))?),
+        None => None,
+    };
+    let tzref = zone.as_ref().map(TimeZone::as_ref).unwrap_or(tz);
+    let (dt, _) = wall_datetime(date, time, tzref, DstChoice::Earliest).map_err(|_| bad())?;
+    Ok(UnixMoment::new(dt.unix_time()))
+}
+
+/// "-PT15M", "P1D", "PT1H30M", "-P1W"
+fn parse_duration(value: &str) -> Result<i64, IcalError> {
+    let bad = || IcalError::BadDuration(value.to_owned());
+    let v = value.trim();
+    let (sign, v) = match v.as_bytes().first() {
+        Some(b'-') => (-1, &v[1..]),
+        Some(b'+') => (1, &v[1..]),
+        _ => (1, v),
+    };
+    let v = v.strip_prefix('P').ok_or_else(bad)?;
+    let mut seconds = 0;
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in v.chars() {
+        let unit = match c {
+            '0'..='9' => {
+                number.push(c);
+                continue;
+            },
+            'T' => {
+                in_time = true;
+                continue;
+            },
+            'W' => 7 * 86400,
+            'D' => 86400,
+            'H' if in_time => 3600,
+            'M' if in_time => 60,
+            'S' if in_time => 1,
+            _ => return Err(bad()),
+        };
+        let n: i64 = number.parse().map_err(|_| bad())?;
+        seconds += n * unit;
+        number.clear();
+    }
+    if ! number.is_empty() { return Err(bad()); }
+    Ok(sign * seconds)
+}
+
+fn parse_trigger(p: &Property, tz: TimeZoneRef) -> Result<Trigger, IcalError> {
+    if p.param("VALUE") == Some("DATE-TIME") {
+        return parse_datetime(p, tz).map(Trigger::Absolute);
+    }
+    let from_end = p.param("RELATED") == Some("END");
+    Ok(Trigger::Relative { seconds: parse_duration(&p.value)?, from_end })
+}
+
+/// Reads the VEVENTs of a calendar. Events that cannot be read are
+/// reported in the second list.
+pub fn parse_ics(text: &str, tz: TimeZoneRef) -> Result<(Vec<IcalEvent>, Vec<String>), IcalError> {
+    let mut events = Vec::new();
+    let mut warnings = Vec::new();
+    let mut stack: Vec<String> = Vec::new();
+    let mut props: Vec<Property> = Vec::new();
+    let mut alarms: Vec<Vec<Property>> = Vec::new();
+    for line in unfold(text) {
+        let Some(p) = Property::parse(&line) else { continue };
+        match p.name.as_str() {
+            "BEGIN" => {
+                let component = p.value.to_uppercase();
+                if component == "VEVENT" {
+                    props.clear();
+                    alarms.clear();
+                } else if component == "VALARM" {
+                    alarms.push(Vec::new());
+                }
+                stack.push(component);
+            },
+            "END" => {
+                let component = p.value.to_uppercase();
+                if stack.pop().as_ref() != Some(&component) {
+                    return Err(IcalError::Structure(format!("unexpected END:{component}")));
+                }
+                if component == "VEVENT" {
+                    match event_from_props(&props, &alarms, tz) {
+                        Ok(ev) => events.push(ev),
+                        Err(e) => {
+                            let uid = props.iter().find(|p| p.name == "UID").map(|p| p.value.as_str()).unwrap_or("?");
+                            warnings.push(format!("skipping event {uid}: {e}"));
+                        },
+                    }
+                }
+            },
+            _ => match stack.last().map(String::as_str) {
+                Some("VEVENT") => props.push(p),
+                Some("VALARM") => if let Some(a) = alarms.last_mut() { a.push(p) },
+                _ => {},
+            },
+        }
+    }
+    if let Some(open) = stack.pop() {
+        return Err(IcalError::Structure(format!("missing END:{open}")));
+    }
+    Ok((events, warnings))
+}
+
+fn event_from_props(props: &[Property], alarms: &[Vec<Property>], tz: TimeZoneRef) -> Result<IcalEvent, IcalError> {
+    let get = |name: &str| props.iter().find(|p| p.name == name);
+    let start = parse_datetime(get("DTSTART").ok_or(IcalError::MissingStart)?, tz)?;
+    let end = match (get("DTEND"), get("DURATION")) {
+        (Some(p), _) => Some(parse_datetime(p, tz)?),
+        (None, Some(p)) => Some(UnixMoment::new(start.seconds() + parse_duration(&p.value)?)),
+        (None, None) => None,
+    };
+    let mut triggers = Vec::new();
+    for alarm in alarms {
+        if let Some(p) = alarm.iter().find(|p| p.name == "TRIGGER") {
+            triggers.push(parse_trigger(p, tz)?);
+        }
+    }
+    Ok(IcalEvent {
+        uid: get("UID").map(|p| p.value.clone()).unwrap_or_default(),
+        summary: get("SUMMARY").map(|p| unescape(&p.value)).unwrap_or(String::from("Event")),
+        description: get("DESCRIPTION").map(|p| unescape(&p.value)).filter(|d| ! d.is_empty()),
+        categories: props.iter()
+            .filter(|p| p.name == "CATEGORIES")
+            .flat_map(|p| p.value.split(',').map(|c| unescape(c.trim())).collect::<Vec<_>>())
+            .filter(|c| ! c.is_empty())
+            .collect(),
+        start,
+        end,
+        rrule: get("RRULE").map(|p| p.value.clone()),
+        triggers,
+    })
+}
+
+const RRULE_DAYS: [(&str, &str); 7] = [
+    ("SU", "Sun"), ("MO", "Mon"), ("TU", "Tue"), ("WE", "Wed"), ("TH", "Thu"), ("FR", "Fri"), ("SA", "Sat"),
+];
+
+/// What an RRULE turns into
+enum Series {
+    Repeats(Schedule),
+    /// the rule cannot be expressed, only the first time is kept
+    Once,
+    Ended,
+}
+
+/// Translates the basic forms of an RRULE into an OnCalendar schedule that
+/// fires at the wall time of `first` in `tz`. When an alarm moves `first`
+/// to another day than the event `start`, BYDAY weekdays move with it.
+/// Parts that cannot be carried over are reported in `warnings`.
+fn rrule_schedule(rrule: &str, start: UnixMoment, first: UnixMoment, now: UnixMoment, tz: TimeZoneRef, warnings: &mut Vec<String>) -> Result<Series, IcalError> {
+    let dt = first.as_datetime(tz).ok_or(IcalError::BadDate(rrule.to_owned()))?;
+    let start_dt = start.as_datetime(tz).ok_or(IcalError::BadDate(rrule.to_owned()))?;
+    let shift = (dt.week_day() + 7 - start_dt.week_day()) as usize % 7;
+    let time = format!("{:02}:{:02}:{:02}", dt.hour(), dt.minute(), dt.second());
+    let mut freq = None;
+    let mut by_day = None;
+    for part in rrule.split(';') {
+        let Some((key, value)) = part.split_once('=') else { continue };
+        match key.to_uppercase().as_str() {
+            "FREQ" => freq = Some(value.to_uppercase()),
+            "BYDAY" => by_day = Some(value.to_uppercase()),
+            "INTERVAL" if value == "1" => {},
+            "WKST" => {},
+            // as written by export_ics, the time of DTSTART again
+            "BYHOUR" if value == dt.hour().to_string() => {},
+            "BYMINUTE" if value == dt.minute().to_string() => {},
+            "BYSECOND" if value == dt.second().to_string() => {},
+            "UNTIL" => {
+                let p = Property { name: String::from("UNTIL"), params: Vec::new(), value: value.to_owned() };
+                if parse_datetime(&p, tz)? < now { return Ok(Series::Ended); }
+                warnings.push(format!("ignoring the end of the series in {rrule}"));
+            },
+            _ => warnings.push(format!("ignoring {part} in {rrule}")),
+        }
+    }
+    let week_days = |list: &str| -> Option<String> {
+        let names: Option<Vec<&str>> = list.split(',')
+            .map(|code| RRULE_DAYS.iter().position(|(c, _)| *c == code).map(|i| RRULE_DAYS[(i + shift) % 7].1))
+            .collect();
+        names.map(|n| n.join(","))
+    };
+    let spec = match freq.as_deref() {
+        Some("DAILY") if by_day.is_none() => format!("*-*-* {time}"),
+        // BYDAY limits a daily rule to those days, same as a weekly one
+        Some("DAILY") | Some("WEEKLY") => {
+            let days = match by_day.as_deref().map(week_days) {
+                Some(Some(days)) => days,
+                Some(None) => return Err(IcalError::BadDate(rrule.to_owned())),
+                None => dow_to_str(dt.week_day()).to_owned(),
+            };
+            format!("{days} *-*-* {time}")
+        },
+        Some("MONTHLY") => format!("*-*-{:02} {time}", dt.month_day()),
+        Some("YEARLY") => format!("*-{:02}-{:02} {time}", dt.month(), dt.month_day()),
+        _ => {
+            warnings.push(format!("unsupported repeat {rrule}, importing the first time only"));
+            return Ok(Series::Once);
+        },
+    };
+    if by_day.is_some() && ! matches!(freq.as_deref(), Some("WEEKLY") | Some("DAILY")) {
+        warnings.push(format!("ignoring BYDAY in {rrule}"));
+    }
+    Schedule::parse(&spec).map(Series::Repeats).map_err(|_| IcalError::BadDate(rrule.to_owned()))
+}
+
+impl IcalEvent {
+    /// Alarm times of the event: one per VALARM, or the start if it has none
+    fn fire_times(&self) -> Vec<UnixMoment> {
+        if self.triggers.is_empty() {
+            return vec![self.start];
+        }
+        self.triggers.iter().map(|t| match *t {
+            Trigger::Absolute(t) => t,
+            Trigger::Relative { seconds, from_end } => {
+                let base = if from_end { self.end.unwrap_or(self.start) } else { self.start };
+                UnixMoment::new(base.seconds() + seconds)
+            },
+        }).collect()
+    }
+}
+
+/// Turns calendar events into alarm specs. Each alarm gets the uid
+/// "UID#n" for its nth VALARM, so that importing again replaces alarms
+/// instead of adding duplicates. Times that have passed are skipped,
+/// unless the event repeats.
+pub fn import_ics(text: &str, now: UnixMoment, tz: TimeZoneRef) -> Result<IcsImport, IcalError> {
+    let (events, warnings) = parse_ics(text, tz)?;
+    let mut import = IcsImport { warnings, ..Default::default() };
+    for ev in events {
+        for (n, fire) in ev.fire_times().into_iter().enumerate() {
+            let schedule = match ev.rrule {
+                Some(ref rrule) => match rrule_schedule(rrule, ev.start, fire, now, tz, &mut import.warnings) {
+                    Ok(Series::Repeats(s)) => Some(s),
+                    Ok(Series::Once) => None,
+                    Ok(Series::Ended) => continue,
+                    Err(e) => {
+                        import.warnings.push(format!("skipping event {}: {e}", ev.uid));
+                        continue;
+                    },
+                },
+                None => None,
+            };
+            let end_t = match schedule {
+                Some(ref s) if fire <= now => match s.next_after(now, tz) {
+                    Some(t) => t,
+                    None => continue,
+                },
+                _ if fire <= now => continue,
+                _ => fire,
+            };
+            let mut spec = AlarmSpec::new(ev.summary.clone(), None, end_t);
+            if ! ev.uid.is_empty() {
+                spec = spec.with_uid(format!("{}#{n}", ev.uid));
+            }
+            if let Some(s) = schedule {
+                spec = spec.with_recurrence(s);
+            }
+            if let Some(ref d) = ev.description {
+                spec = spec.with_notes(d.clone());
+            }
+            if ! ev.categories.is_empty() {
+                spec = spec.with_tags(ev.categories.clone());
+            }
+            import.specs.push(spec);
+        }
+    }
+    Ok(import)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(';', "\\;").replace(',', "\\,").replace('\n', "\\n")
+}
+
+fn utc_stamp(t: UnixMoment) -> String {
+    match tz::UtcDateTime::from_timespec(t.seconds(), 0) {
+        Ok(dt) => format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", dt.year(), dt.month(), dt.month_day(), dt.hour(), dt.minute(), dt.second()),
+        Err(_) => String::from("19700101T000000Z"),
+    }
+}
+
+/// Appends a content line, folded at 75 octets
+fn push_line(out: &mut String, line: &str) {
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// Writes pending alarms as an iCalendar file, one VEVENT with a VALARM at
+/// its start per alarm. Schedules become an RRULE, alarms without a uid get
+/// one made from their id and time.
+pub fn export_ics(alarms: &[PendingAlarm], now: UnixMoment) -> String {
+    let mut out = String::new();
+    push_line(&mut out, "BEGIN:VCALENDAR");
+    push_line(&mut out, "VERSION:2.0");
+    push_line(&mut out, "PRODID:-//clockd//clockd//EN");
+    for a in alarms {
+        let r = &a.record;
+        let uid = r.uid.clone().unwrap_or_else(|| format!("clockd-{}-{}@clockd", a.id.as_nanos(), a.end_t.seconds()));
+        let rrule = match r.repeat.as_deref().map(Schedule::parse) {
+            Some(Ok(s)) => Some(s.to_rrule()),
+            _ if r.daily => Some(String::from("FREQ=DAILY")),
+            _ => None,
+        };
+        push_line(&mut out, "BEGIN:VEVENT");
+        push_line(&mut out, &format!("UID:{}", escape(&uid)));
+        push_line(&mut out, &format!("DTSTAMP:{}", utc_stamp(now)));
+        push_line(&mut out, &format!("DTSTART:{}", utc_stamp(a.end_t)));
+        push_line(&mut out, &format!("SUMMARY:{}", escape(&r.name)));
+        if let Some(ref notes) = r.notes {
+            push_line(&mut out, &format!("DESCRIPTION:{}", escape(notes)));
+        }
+        if ! r.tags.is_empty() {
+            let tags: Vec<String> = r.tags.iter().map(|t| escape(t)).collect();
+            push_line(&mut out, &format!("CATEGORIES:{}", tags.join(",")));
+        }
+        if let Some(rrule) = rrule {
+            push_line(&mut out, &format!("RRULE:{rrule}"));
+        }
+        push_line(&mut out, "BEGIN:VALARM");
+        push_line(&mut out, "ACTION:DISPLAY");
+        push_line(&mut out, "TRIGGER:PT0S");
+        push_line(&mut out, &format!("DESCRIPTION:{}", escape(&r.name)));
+        push_line(&mut out, "END:VALARM");
+        push_line(&mut out, "END:VEVENT");
+    }
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+    use crate::fixture::*;
+
+    const ICS: &str = "BEGIN:VCALENDAR\r
+BEGIN:VEVENT\r
+UID:standup@example.com\r
+SUMMARY:Standup\\, daily\r
+DTSTART;TZID=EST5EDT,M3.2.0,M11.1.0:20240301T093000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR\r
+BEGIN:VALARM\r
+TRIGGER:-PT5M\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:review@example.com\r
+SUMMARY:Quarterly\r
+  review\r
+DTSTART:20240307T150000Z\r
+DTEND:20240307T160000Z\r
+BEGIN:VALARM\r
+TRIGGER;RELATED=END:-PT10M\r
+END:VALARM\r
+BEGIN:VALARM\r
+TRIGGER:-PT12H\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:old@example.com\r
+DTSTART:20240101T090000Z\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn durations() {
+        assert_eq!(-300, parse_duration("-PT5M").unwrap());
+        assert_eq!(90 * 60, parse_duration("PT1H30M").unwrap());
+        assert_eq!(8 * 86400, parse_duration("P1W1D").unwrap());
+        assert!(parse_duration("15M").is_err());
+    }
+
+    /// The alarms the specs turn into at NOW. Alarms count from the real
+    /// clock, so their end times are moved back to count from NOW.
+    fn alarms(specs: Vec<AlarmSpec>) -> Vec<Alarm> {
+        specs.into_iter()
+            .map(|s| {
+                let mut a = s.into_alarm(UnixMoment::new(NOW), eastern().as_ref(), ResolveOptions::default()).unwrap();
+                a.end_t = UnixMoment::new(NOW) + (a.end_t - a.creation_t);
+                a
+            })
+            .collect()
+    }
+
+    #[test]
+    fn imports_events() {
+        let import = import_ics(ICS, UnixMoment::new(NOW), eastern().as_ref()).unwrap();
+        let alarms = alarms(import.specs);
+        assert_eq!(3, alarms.len());
+        // standup repeats, next is friday 09:25
+        assert_eq!("Standup, daily", alarms[0].name);
+        assert_eq!(UnixMoment::new(NOW + 2 * 86400 - 155 * 60), alarms[0].end_t);
+        assert_eq!(Some("Mon,Wed,Fri *-*-* 09:25:00"), alarms[0].recurrence.as_ref().map(|s| s.to_string()).as_deref());
+        assert_eq!(Some("standup@example.com#0"), alarms[0].uid.as_deref());
+        // 10 minutes before the end
+        assert_eq!("Quarterly review", alarms[1].name);
+        assert_eq!(UnixMoment::new(1709827200 - 600), alarms[1].end_t);
+        assert_eq!(None, alarms[1].recurrence);
+        assert_eq!(Some("review@example.com#0"), alarms[1].uid.as_deref());
+        // 12 hours before the start, 22:00 EST on the 6th
+        assert_eq!("Quarterly review", alarms[2].name);
+        assert_eq!(UnixMoment::new(1709823600 - 12 * 3600), alarms[2].end_t);
+        assert_eq!(None, alarms[2].recurrence);
+        assert_eq!(Some("review@example.com#1"), alarms[2].uid.as_deref());
+    }
+
+    #[test]
+    fn trigger_moves_weekdays() {
+        let ics = "BEGIN:VEVENT\r\nUID:gym\r\nSUMMARY:Gym\r\nDTSTART;TZID=EST5EDT,M3.2.0,M11.1.0:20240301T080000\r\nRRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR\r\nBEGIN:VALARM\r\nTRIGGER:-PT12H\r\nEND:VALARM\r\nEND:VEVENT\r\n";
+        let import = import_ics(ics, UnixMoment::new(NOW), eastern().as_ref()).unwrap();
+        let alarms = alarms(import.specs);
+        assert_eq!(1, alarms.len());
+        // the evening before each session, next is thu 20:00 EST
+        assert_eq!(Some("Sun,Tue,Thu *-*-* 20:00:00"), alarms[0].recurrence.as_ref().map(|s| s.to_string()).as_deref());
+        assert_eq!(UnixMoment::new(1709859600), alarms[0].end_t);
+    }
+
+    #[test]
+    fn unescapes_text() {
+        assert_eq!("a, b; c\\d\ne", unescape("a\\, b\\; c\\\\d\\ne"));
+        assert_eq!("C:\\new", unescape("C:\\\\new"));
+        assert_eq!("one\\n, two\nthree", unescape(&escape("one\\n, two\nthree")));
+    }
+
+    #[test]
+    fn rejects_unbalanced() {
+        assert!(import_ics("BEGIN:VEVENT\nUID:x\n", UnixMoment::new(NOW), eastern().as_ref()).is_err());
+    }
+
+    #[test]
+    fn export_imports_again() {
+        let record = AlarmRecord::from_json(r#"{"name": "Standup, daily", "time": 0, "repeat": "30 9 * * 1-5"}"#).unwrap();
+        // Thu 2024-03-07 09:30 EST
+        let pending = PendingAlarm { id: UnixMoment::new(NOW), end_t: UnixMoment::new(1709821800), managed: false, ringing: false, record };
+        let text = export_ics(&[pending], UnixMoment::new(NOW));
+        assert!(text.contains("UID:clockd-1709744400000000000-1709821800@clockd\r\n"), "{text}");
+        assert!(text.contains("SUMMARY:Standup\\, daily\r\n"), "{text}");
+        let import = import_ics(&text, UnixMoment::new(NOW), eastern().as_ref()).unwrap();
+        assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+        let alarms = alarms(import.specs);
+        assert_eq!(1, alarms.len());
+        assert_eq!("Standup, daily", alarms[0].name);
+        assert_eq!(UnixMoment::new(1709821800), alarms[0].end_t);
+        assert_eq!(Some("Mon,Tue,Wed,Thu,Fri *-*-* 09:30:00"), alarms[0].recurrence.as_ref().map(|s| s.to_string()).as_deref());
+        assert_eq!(Some("clockd-1709744400000000000-1709821800@clockd#0"), alarms[0].uid.as_deref());
+    }
+}
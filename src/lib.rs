@@ -7,13 +7,69 @@ use util::*;
 use std::path::PathBuf;
 mod alarm;
 pub use alarm::*;
+mod timepart;
+pub use timepart::*;
+mod tz_display;
+pub use tz_display::*;
+mod workcalendar;
+pub use workcalendar::*;
+mod solar;
+pub use solar::*;
+mod ical;
+pub use ical::*;
+mod record;
+pub use record::*;
+mod player;
+pub use player::*;
+mod sounds;
+pub use sounds::*;
+mod speech;
+pub use speech::*;
+mod chime;
+pub use chime::*;
+#[cfg(test)]
+mod fixture;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AppCommand {
-    Add(AlarmSpec),
+    Add(Box<AlarmSpec>),
     Remove(UnixMoment),
     Acknowledge,
+    /// acknowledge one ringing alarm, by its creation time
+    AcknowledgeAlarm(UnixMoment),
+    /// acknowledge one ringing alarm and have it ring again after a while
+    Snooze(UnixMoment, Duration),
     SwitchDisplay,
+    /// turn the hourly chimes on or off
+    Chimes(bool),
+    /// keep chimes quiet while set
+    DoNotDisturb(bool),
+    /// answered on the command socket with a json line of Vec<PendingAlarm>
+    List,
+    /// import an .ics file that the daemon can read
+    ImportIcs(PathBuf),
+    /// replace all alarms synced from the calendar directory
+    SyncManaged(Vec<AlarmSpec>),
+    /// apply all of the commands or, if one of them fails, none. Answered
+    /// on the command socket with a json line of Vec<CommandOutcome>.
+    Batch(Vec<AppCommand>),
+}
+
+/// Result of one command of an AppCommand::Batch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommandOutcome {
+    Done,
+    Failed(String),
+    /// rolled back because another command of the batch failed
+    NotApplied,
+}
+
+/// When an AlarmSpec should go off
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum AlarmTime {
+    At(UnixMoment),
+    /// time expression, resolved by the daemon
+    Expr(String),
 }
 
 /// Struct used by external processes to pass a new Alarm to the daemon
@@ -21,12 +77,132 @@ pub enum AppCommand {
 pub struct AlarmSpec {
     name: String,
     soundfile: Option<PathBuf>,
-    end_t: UnixMoment, 
+    end_t: AlarmTime,
+    #[serde(default)]
+    recurrence: Option<Schedule>,
+    #[serde(default)]
+    daily: Option<String>,
+    #[serde(default)]
+    workdays_only: bool,
+    /// identifies imported alarms, an alarm replaces a pending one with the same uid
+    #[serde(default)]
+    uid: Option<String>,
+    /// synced from the calendar directory, see AppCommand::SyncManaged
+    #[serde(default)]
+    managed: bool,
+    #[serde(default)]
+    sound_policy: SoundPolicy,
+    #[serde(default)]
+    sound_selection: SoundSelection,
+    #[serde(default)]
+    notes: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
 }
 
 impl AlarmSpec {
+    /// A one-off alarm with the default sound settings
+    fn with_time(name: String, soundfile: Option<PathBuf>, end_t: AlarmTime) -> Self {
+        Self {name, soundfile, end_t, recurrence: None, daily: None, workdays_only: false, uid: None, managed: false, sound_policy: SoundPolicy::Once, sound_selection: SoundSelection::Random, notes: None, tags: Vec::new()}
+    }
     pub fn new(name: String, soundfile: Option<PathBuf>, end_t: UnixMoment) -> Self {
-        Self {name, soundfile, end_t}
+        Self::with_time(name, soundfile, AlarmTime::At(end_t))
+    }
+    pub fn from_expr(name: String, soundfile: Option<PathBuf>, expr: String) -> Self {
+        Self::with_time(name, soundfile, AlarmTime::Expr(expr))
+    }
+    /// Makes the alarm repeat on the schedule after it first goes off
+    pub fn with_recurrence(mut self, schedule: Schedule) -> Self {
+        self.recurrence = Some(schedule);
+        self
+    }
+    /// Makes the alarm repeat by resolving the expression again after it
+    /// goes off, for times that move from day to day like "sunset"
+    pub fn repeat_daily(mut self, expr: String) -> Self {
+        self.daily = Some(expr);
+        self
+    }
+    /// Plays the sound again until the alarm is acknowledged
+    pub fn loop_sound(mut self) -> Self {
+        self.sound_policy = SoundPolicy::Loop;
+        self
+    }
+    /// How to pick a file when the sound is a directory, playlist or glob
+    pub fn with_sound_selection(mut self, selection: SoundSelection) -> Self {
+        self.sound_selection = selection;
+        self
+    }
+    /// Free text about the alarm, for announcements and notifications
+    pub fn with_notes(mut self, notes: String) -> Self {
+        self.notes = Some(notes);
+        self
+    }
+    /// Labels for hooks and filters, e.g. "work"
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+    pub fn with_uid(mut self, uid: String) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+    /// Marks the alarm as kept in sync with a calendar
+    pub fn managed(mut self) -> Self {
+        self.managed = true;
+        self
+    }
+    /// Makes a recurring alarm skip weekends and holidays
+    pub fn workdays_only(mut self) -> Self {
+        self.workdays_only = true;
+        self
+    }
+    /// Validates the spec, resolving a time expression relative to `now`
+    pub fn into_alarm(self, now: UnixMoment, tz: tz::TimeZoneRef, options: ResolveOptions) -> Result<Alarm, AlarmSpecError> {
+        let end_t = match self.end_t {
+            AlarmTime::At(t) => t,
+            AlarmTime::Expr(ref expr) => resolve_with(expr, now, tz, options).map_err(AlarmSpecError::BadTime)?.moment,
+        };
+        let end_in = now.duration_until(end_t)
+            .ok_or(AlarmSpecError::EndTimeInPast)?;
+        // a daily expression that never lands on a working day would never ring again
+        if let (Some(ref expr), true) = (&self.daily, self.workdays_only) {
+            Alarm::next_daily(expr, now, tz, options, true).map_err(AlarmSpecError::BadTime)?;
+        }
+        // sound names are looked up in the sound library when the alarm rings
+        if let Some(ref p) = self.soundfile {
+            if p.components().count() > 1 && ! p.exists() && ! is_glob(p) { return Err(AlarmSpecError::SoundfileNotExist); }
+        }
+        let mut alarm = Alarm::new_from_durations(self.name, self.soundfile, end_in);
+        alarm.recurrence = self.recurrence;
+        alarm.daily = self.daily;
+        alarm.workdays_only = self.workdays_only;
+        alarm.uid = self.uid;
+        alarm.managed = self.managed;
+        alarm.sound_policy = self.sound_policy;
+        alarm.sound_selection = self.sound_selection;
+        alarm.notes = self.notes;
+        alarm.tags = self.tags;
+        Ok(alarm)
+    }
+}
+
+impl std::fmt::Display for AlarmSpec {
+    /// e.g. "Standup: Fri 08 Mar 09:25 EST, repeats Mon,Wed,Fri *-*-* 09:25:00"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.name)?;
+        let tz = tz::TimeZone::local().ok();
+        match (&self.end_t, tz) {
+            (AlarmTime::At(t), Some(tz)) => match t.as_datetime(tz.as_ref()) {
+                Some(dt) => write!(f, "{}", humanize_datetime_full(dt))?,
+                None => write!(f, "{}", t.seconds())?,
+            },
+            (AlarmTime::At(t), None) => write!(f, "{}", t.seconds())?,
+            (AlarmTime::Expr(expr), _) => write!(f, "{expr}")?,
+        }
+        if let Some(ref s) = self.recurrence {
+            write!(f, ", repeats {s}")?;
+        }
+        Ok(())
     }
 }
 
@@ -34,13 +210,8 @@ impl TryFrom<AlarmSpec> for Alarm {
     type Error = AlarmSpecError;
 
     fn try_from(value: AlarmSpec) -> Result<Self, Self::Error> {
-        let now = UnixMoment::now();
-        let end_in = now.duration_until(value.end_t)
-            .ok_or(AlarmSpecError::EndTimeInPast)?;
-        if let Some(ref p) = value.soundfile {
-            if ! p.exists() { return Err(AlarmSpecError::SoundfileNotExist); }
-        }
-        Ok(Alarm::new_from_durations(value.name, value.soundfile, end_in))
+        let tz = tz::TimeZone::local().map_err(|e| AlarmSpecError::BadTime(e.into()))?;
+        value.into_alarm(UnixMoment::now(), tz.as_ref(), ResolveOptions::default())
     }
 }
 
@@ -48,21 +219,49 @@ impl TryFrom<AlarmSpec> for Alarm {
 pub enum AlarmSpecError {
     EndTimeInPast,
     SoundfileNotExist,
+    BadTime(ParseError),
 }
 impl std::error::Error for AlarmSpecError {}
 impl std::fmt::Display for AlarmSpecError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{self:?}")
+        match self {
+            AlarmSpecError::EndTimeInPast => write!(f, "End time is in the past"),
+            AlarmSpecError::SoundfileNotExist => write!(f, "Sound file does not exist"),
+            AlarmSpecError::BadTime(e) => write!(f, "{e}"),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum AppEvent {
-    Ring(Alarm),
+    /// the alarm's soundfile is the file picked to play, if any
+    Ring(Box<Alarm>),
+    /// the alarm goes off soon, see clockd --warn-before
+    Warn(Box<Alarm>),
+    /// an alarm was added by a command, sent after its AlarmListUpdate
+    Added(Box<Alarm>),
     Minute(tz::DateTime),
     Tick,
     AlarmListUpdate,
     Ack,
+    /// one of several ringing alarms was acknowledged
+    AckAlarm(UnixMoment),
+    /// a ringing alarm was snoozed, a copy of it is pending
+    Snooze(UnixMoment),
     SwitchDisplay,
+    Chimes(bool),
+    DoNotDisturb(bool),
     NewListener,
+    /// a sound could not be played, e.g. because no player is installed
+    AudioError(String),
+    Playback(PlaybackState),
+}
+
+/// What the audio task is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaybackState {
+    Idle,
+    Playing,
+    /// the player kept failing, the sound was given up
+    Failed,
 }
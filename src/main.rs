@@ -5,11 +5,12 @@ use std::sync::{Arc, RwLock};
 
 use clockd::*;
 
-mod tz_display;
-use tz_display::*;
 use tz::{DateTime, TimeZone, TimeZoneRef, UtcDateTime};
 
 mod audio;
+mod mpv;
+mod notify;
+mod hooks;
 mod fdrecv;
 mod error;
 use error::*;
@@ -19,10 +20,15 @@ mod waybar;
 mod util;
 use util::*;
 mod commands;
+mod vdir;
+mod apply;
+use apply::*;
 mod parker;
 mod webapp;
 mod prelude;
 use prelude::*;
+#[cfg(test)]
+mod fixture;
 
 use crate::commands::start_command_socket;
 
@@ -61,10 +67,52 @@ enum MainLoopEvent {
     Minute,
     Tick,
     AlarmTimer,
-    Command(AppCommand),
+    Command(Box<AppCommand>),
+    /// a batch from the command socket, which waits for the outcomes
+    Batch(Vec<AppCommand>, Sender<Vec<CommandOutcome>>),
     NewListener,
 }
 
+fn pending_ids(alarm_list: &RwLock<AlarmList>) -> Vec<UnixMoment> {
+    alarm_list.read().unwrap().pending().map(|a| a.creation_t).collect()
+}
+
+/// Announces each alarm that is pending now but was not `before`. Calendar
+/// syncs re-add their alarms and a snoozed alarm comes back already warned,
+/// neither counts as added.
+async fn broadcast_added(alarm_list: &RwLock<AlarmList>, event_tx: &broadcast::Sender<AppEvent>, before: &[UnixMoment]) {
+    let added: Vec<Alarm> = alarm_list.read().unwrap().pending()
+        .filter(|a| ! a.managed && ! a.warned && ! before.contains(&a.creation_t))
+        .cloned()
+        .collect();
+    for a in added {
+        event_tx.broadcast(AppEvent::Added(Box::new(a))).await;
+    }
+}
+
+/// Applies the batch under one write lock and announces the changes once
+async fn apply_batch_and_broadcast(
+    alarm_list: &RwLock<AlarmList>,
+    event_tx: &broadcast::Sender<AppEvent>,
+    cmds: Vec<AppCommand>,
+    now: UnixMoment,
+    tz: TimeZoneRef<'_>,
+    options: ResolveOptions<'_>,
+    ) -> Vec<CommandOutcome> {
+    let before = pending_ids(alarm_list);
+    let (outcomes, changes) = apply_batch(&mut alarm_list.write().unwrap(), cmds, now, tz, options);
+    for (n, outcome) in outcomes.iter().enumerate() {
+        if let CommandOutcome::Failed(e) = outcome {
+            error!("batch: command {}: {e}, nothing was applied", n + 1);
+        }
+    }
+    for change in changes {
+        event_tx.broadcast(change.event()).await;
+    }
+    broadcast_added(alarm_list, event_tx, &before).await;
+    outcomes
+}
+
 fn d(s: u64) -> Duration { Duration::from_secs(s) }
 
 async fn test_driver(cmd_tx: Sender<AppCommand>) {
@@ -72,17 +120,17 @@ async fn test_driver(cmd_tx: Sender<AppCommand>) {
     let now = UnixMoment::now();
 
     let a = AlarmSpec::new(String::from("test1"), None, now + d(5));
-    cmd_tx.send(AppCommand::Add(a)).await;
+    cmd_tx.send(AppCommand::Add(Box::new(a))).await;
 
     let a = AlarmSpec::new(String::from("test2"), Some("/home/jeff/.local/alarms/default".into()), now + d(10));
-    cmd_tx.send(AppCommand::Add(a)).await;
+    cmd_tx.send(AppCommand::Add(Box::new(a))).await;
 
     // sleep(d(8)).await;
     // trace!("send ack");
     // cmd_tx.send(AppCommand::Acknowledge).await;
 
     let a = AlarmSpec::new(String::from("test3"), None, now + d(3620));
-    cmd_tx.send(AppCommand::Add(a)).await;
+    cmd_tx.send(AppCommand::Add(Box::new(a))).await;
 
     sleep(d(15)).await;
     trace!("send ack");
@@ -100,10 +148,14 @@ async fn main_loop(
     c: &mut Config, 
     // cmd_tx: Sender<AppCommand>,
     mut cmd_rx: Receiver<AppCommand>,
+    batch_rx: Receiver<(Vec<AppCommand>, Sender<Vec<CommandOutcome>>)>,
     mut alarm_list: Arc<RwLock<AlarmList>>,
     mut event_tx: broadcast::Sender<AppEvent>,
+    calendar: WorkCalendar,
+    sounds: SoundLibrary,
     ) -> Anything<()> {
     let local_tz = TimeZone::local().unwrap();
+    let options = ResolveOptions { calendar: Some(&calendar), location: c.location, ..Default::default() };
     // fd passing synchronous task
     let (fd_parker, fd_nudger) = parker::Parker::new();
     let fd_socket = c.fd_socket.take().unwrap();
@@ -111,8 +163,9 @@ async fn main_loop(
 
     // let mut waybar_display = waybar::WaybarDisplay::new(local_tz);
 
-    let mut cmd_stream = cmd_rx.map(MainLoopEvent::Command);
+    let mut cmd_stream = cmd_rx.map(|cmd| MainLoopEvent::Command(Box::new(cmd)));
     let mut fd_stream = fd_parker.map(|_| MainLoopEvent::NewListener);
+    let mut batch_stream = batch_rx.map(|(cmds, reply)| MainLoopEvent::Batch(cmds, reply));
     let mut alarm_timer = Timer::never();
     let mut now = UnixMoment::now();
     loop {
@@ -121,10 +174,24 @@ async fn main_loop(
         // set timer for next alarm
         let mut x = alarm_list.write().unwrap();
         let alarm_events = x.update_with_current_time(now);
+        // queue the next occurrence of recurring alarms that went off
+        for ae in alarm_events.iter() {
+            if let AlarmEvent::Now(a) = ae {
+                if let Some(next) = a.next_occurrence(now, local_tz.as_ref(), options) {
+                    x.add(next);
+                }
+            }
+        }
+        let warn_before = c.warn_before.map(Duration::from_secs);
+        let warnings = warn_before.map(|before| x.take_warnings(now, before)).unwrap_or_default();
+        let next_warning = warn_before.and_then(|before| x.next_warning(before));
         let next_alarm = x.next_alarm();
         let new_duration = next_alarm
-            .map(|a| now.duration_until(a.end_t))
-            .flatten()
+            .map(|a| a.end_t)
+            .into_iter()
+            .chain(next_warning)
+            .min()
+            .and_then(|t| now.duration_until(t))
             .unwrap_or(Duration::MAX);
         // set up tick timer
         let tick_stream = if new_duration <= ONE_HOUR || x.lead_alarming().is_some() {
@@ -134,13 +201,22 @@ async fn main_loop(
         }.map(|_| MainLoopEvent::Tick);
         drop(x);
 
+        for a in warnings {
+            event_tx.broadcast(AppEvent::Warn(Box::new(a))).await;
+        }
+
         // process alarm events
         for ae in alarm_events.into_iter() {
             match ae {
                 AlarmEvent::Started(a) => {
                 },
-                AlarmEvent::Now(a) => {
-                    event_tx.broadcast(AppEvent::Ring(a)).await;
+                AlarmEvent::Now(mut a) => {
+                    let (sound, warning) = sounds.sound_for(a.soundfile.as_deref(), a.sound_selection, a.sound_turn);
+                    if let Some(w) = warning {
+                        warn!("{}: {w}", a.name);
+                    }
+                    a.soundfile = sound;
+                    event_tx.broadcast(AppEvent::Ring(Box::new(a))).await;
                 },
             }
         }
@@ -151,6 +227,7 @@ async fn main_loop(
 
         let mut s = next_minute
             .merge(&mut cmd_stream)
+            .merge(&mut batch_stream)
             .merge(&mut fd_stream)
             .merge(tick_stream)
             .merge(alarm_stream);
@@ -172,37 +249,26 @@ async fn main_loop(
                 // NOTE: this event should already have been broadcast
             },
             MainLoopEvent::Command(cmd) => {
-                match cmd {
-                    AppCommand::Add(spec) => {
-                        let alarm = Alarm::try_from(spec);
-                        if let Ok(a) = alarm {
-                            let mut x = alarm_list.write().unwrap();
-                            x.add(a);
-                            drop(x);
-                            event_tx.broadcast(AppEvent::AlarmListUpdate).await;
-                        } else {
-                            error!("add: bad alarm spec");
-                        }
+                let cmd = *cmd;
+                if let AppCommand::Batch(cmds) = cmd {
+                    // sent without a way to answer, e.g. by a script writing to the socket
+                    apply_batch_and_broadcast(&alarm_list, &event_tx, cmds, now, local_tz.as_ref(), options).await;
+                    continue;
+                }
+                let before = pending_ids(&alarm_list);
+                let result = apply_command(&mut alarm_list.write().unwrap(), cmd, now, local_tz.as_ref(), options);
+                match result {
+                    Ok(applied) => {
+                        event_tx.broadcast(applied.event()).await;
+                        broadcast_added(&alarm_list, &event_tx, &before).await;
                     },
-                    AppCommand::Acknowledge => {
-                        let mut x = alarm_list.write().unwrap();
-                        x.acknowledge();
-                        drop(x);
-                        event_tx.broadcast(AppEvent::Ack).await;
-                        
-                    }
-                    AppCommand::Remove(creation_t) => {
-                        let mut x = alarm_list.write().unwrap();
-                        x.remove(creation_t);
-                        drop(x);
-                        event_tx.broadcast(AppEvent::AlarmListUpdate).await;
-                    }
-                    AppCommand::SwitchDisplay => {
-                        event_tx.broadcast(AppEvent::SwitchDisplay).await;
-                    }
-                    _ => {},
+                    Err(e) => error!("{e}"),
                 }
             },
+            MainLoopEvent::Batch(cmds, reply) => {
+                let outcomes = apply_batch_and_broadcast(&alarm_list, &event_tx, cmds, now, local_tz.as_ref(), options).await;
+                reply.send(outcomes).await;
+            },
             MainLoopEvent::NewListener => {
                 event_tx.broadcast(AppEvent::NewListener).await;
             }
@@ -217,20 +283,61 @@ async fn main() -> Anything<()> {
     let mut c: Config = get_config()?;
     setup(&c);
     let cmd_socket = c.cmd_socket.take().unwrap();
+    let holidays = c.holidays.as_ref().map(|p| std::path::Path::new(p.as_os_str()));
+    let calendar = WorkCalendar::load(holidays, &c.workdays)?;
+    let sounds_dir = c.sounds_dir.as_ref().map(|d| std::path::PathBuf::from(d.as_os_str()))
+        .or_else(SoundLibrary::default_dir)
+        .unwrap_or_default();
+    let sounds = SoundLibrary {
+        dir: sounds_dir,
+        default: Some(c.default_sound.clone()),
+        fallback: c.fallback_sound.clone(),
+    };
     let (cmd_tx, cmd_rx) = channel::unbounded::<AppCommand>();
+    let (batch_tx, batch_rx) = channel::unbounded();
     // TODO: adjust channel capaacity
     let (event_tx, mut event_rx) = broadcast::broadcast::<AppEvent>(2);
 
     // TEST:
     // spawn(test_driver(cmd_tx.clone())); 
 
-    spawn(commands::start_command_socket(cmd_socket, cmd_tx.clone()));
+    if let Some(dir) = c.vdir.take() {
+        let dir = std::path::PathBuf::from(dir.as_os_str());
+        let tx = cmd_tx.clone();
+        async_std::task::spawn_blocking(move || vdir::start_vdir_watch(dir, tx));
+    }
     let alarm_list = Arc::new(RwLock::new(AlarmList::new()));
+    spawn(commands::start_command_socket(cmd_socket, cmd_tx.clone(), batch_tx, alarm_list.clone()));
     let tz = TimeZone::local().expect("Could not get local time zone");
     // let webstate = webapp::WebState::new(cmd_tx, alarm_list.alarms(), tz);
     // spawn(webapp::server(webstate, c.port));
-    spawn(audio::start_audio_task(event_rx.clone()));
-    spawn(waybar::waybar_display_server(alarm_list.clone(), event_rx.clone(), tz::TimeZone::local().unwrap())); 
+    let fade = (c.fade_in > 0).then_some((c.fade_start, Duration::from_secs(c.fade_in)));
+    let speech = c.speak.as_deref().and_then(|s| audio::Speech::new(s, c.speak_text.clone(), c.speak_warning.clone(), c.speak_when));
+    let audio_config = audio::AudioConfig::new(c.player.as_deref(), c.volume, c.loops, c.sound_queue, fade)
+        .with_speech(speech)
+        .with_chimes(audio::Chimes {
+            config: ChimeConfig {
+                interval: c.chime_every,
+                hour_sound: c.chime_sound.clone(),
+                half_sound: c.chime_half_sound.clone(),
+                quarter_sound: c.chime_quarter_sound.clone(),
+                strike: c.chime_strikes,
+                quiet: c.quiet_hours,
+            },
+            sounds: SoundLibrary::new(sounds.dir.clone()),
+            volume: c.chime_volume.unwrap_or(c.volume),
+            on: c.chimes,
+        });
+    spawn(audio::start_audio_task(event_rx.clone(), event_tx.clone(), audio_config));
+    if c.notify {
+        spawn(notify::start_notifier(event_rx.clone(), cmd_tx.clone(), Duration::from_secs(c.snooze * 60)));
+    }
+    let hooks_dir = c.hooks_dir.as_ref().map(|d| std::path::PathBuf::from(d.as_os_str()))
+        .or_else(hooks::Hooks::default_dir);
+    let hooks = hooks::Hooks::new(hooks_dir, c.hooks.clone(), Duration::from_secs(c.hook_timeout));
+    spawn(hooks::run_hooks(hooks, event_rx.clone()));
+    let sun = c.location.filter(|_| c.sun_tooltip);
+    spawn(waybar::waybar_display_server(alarm_list.clone(), event_rx.clone(), tz::TimeZone::local().unwrap(), sun)); 
 
     // manaully keep the event rx drained
     spawn(async move {
@@ -240,7 +347,7 @@ async fn main() -> Anything<()> {
         unreachable!()
     });
 
-    main_loop(&mut c, cmd_rx, alarm_list, event_tx).await;
+    main_loop(&mut c, cmd_rx, batch_rx, alarm_list, event_tx, calendar, sounds).await;
     Ok(())
 }
 
@@ -0,0 +1,169 @@
+use crate::*;
+use async_std::os::unix::net::UnixStream;
+use serde_json::{json, Value};
+use std::io::{Error, ErrorKind};
+
+/// time between two volume changes of a fade
+const FADE_STEP: Duration = Duration::from_millis(250);
+/// how often to look for the socket while mpv starts
+const CONNECT_RETRY: Duration = Duration::from_millis(50);
+
+/// Ramps the volume from `from` to `to` percent over `over`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fade {
+    pub from: u8,
+    pub to: u8,
+    pub over: Duration,
+}
+
+impl Fade {
+    /// Volumes to set one FADE_STEP apart, the last one is `to`
+    pub fn steps(&self) -> Vec<u8> {
+        let n = self.over.as_millis().div_ceil(FADE_STEP.as_millis()).max(1) as i64;
+        let (from, to) = (self.from as i64, self.to as i64);
+        (1..=n).map(|i| (from + (to - from) * i / n) as u8).collect()
+    }
+}
+
+/// A connection to mpv's JSON IPC socket
+pub struct MpvIpc {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl MpvIpc {
+    /// Connects to the socket, waiting up to `timeout` for mpv to create it
+    pub async fn connect(path: &std::path::Path, timeout: Duration) -> std::io::Result<Self> {
+        let deadline = Instant::now() + timeout;
+        let stream = loop {
+            match UnixStream::connect(path).await {
+                Ok(s) => break s,
+                Err(e) if Instant::now() >= deadline => return Err(e),
+                Err(_) => { Timer::after(CONNECT_RETRY).await; },
+            }
+        };
+        Ok(Self { reader: BufReader::new(stream.clone()), writer: stream, next_id: 1 })
+    }
+
+    /// Sends a command and waits for its reply, skipping the events mpv
+    /// sends in between. Returns the reply's data.
+    pub async fn command(&mut self, args: Value) -> std::io::Result<Value> {
+        use async_std::io::WriteExt;
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut line = json!({ "command": args, "request_id": id }).to_string();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        loop {
+            let mut reply = String::new();
+            if self.reader.read_line(&mut reply).await? == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "mpv closed the IPC socket"));
+            }
+            let reply: Value = serde_json::from_str(&reply).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            if reply["request_id"] != id {
+                continue;
+            }
+            return match reply["error"].as_str() {
+                Some("success") => Ok(reply["data"].clone()),
+                error => Err(Error::other(format!("mpv: {}", error.unwrap_or("no error in reply")))),
+            };
+        }
+    }
+
+    pub async fn set_volume(&mut self, volume: u8) -> std::io::Result<()> {
+        self.command(json!(["set_property", "volume", volume])).await.map(drop)
+    }
+
+    pub async fn set_pause(&mut self, paused: bool) -> std::io::Result<()> {
+        self.command(json!(["set_property", "pause", paused])).await.map(drop)
+    }
+
+    pub async fn quit(&mut self) -> std::io::Result<()> {
+        self.command(json!(["quit"])).await.map(drop)
+    }
+}
+
+/// Fades the volume of the mpv listening on `socket`. Ends early when mpv
+/// goes away.
+pub async fn fade_in(socket: &std::path::Path, fade: Fade, timeout: Duration) -> std::io::Result<()> {
+    let mut ipc = MpvIpc::connect(socket, timeout).await?;
+    for volume in fade.steps() {
+        Timer::after(FADE_STEP).await;
+        ipc.set_volume(volume).await?;
+    }
+    Ok(())
+}
+
+/// Silences the mpv listening on `socket` and asks it to quit
+pub async fn stop(socket: &std::path::Path, timeout: Duration) -> std::io::Result<()> {
+    let mut ipc = MpvIpc::connect(socket, timeout).await?;
+    ipc.set_pause(true).await?;
+    ipc.quit().await
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+    use async_std::os::unix::net::UnixListener;
+
+    /// Stands in for mpv: answers every command and records it, with an
+    /// event before each reply
+    async fn fake_mpv(listener: UnixListener, log: Arc<Mutex<Vec<Value>>>) {
+        use async_std::io::WriteExt;
+        while let Some(Ok(stream)) = listener.incoming().next().await {
+            let log = log.clone();
+            spawn(async move {
+                let mut writer = stream.clone();
+                let mut lines = BufReader::new(stream).lines();
+                while let Some(Ok(line)) = lines.next().await {
+                    let request: Value = serde_json::from_str(&line).unwrap();
+                    let error = if request["command"][0] == "bogus" { "invalid parameter" } else { "success" };
+                    log.lock().await.push(request["command"].clone());
+                    let reply = format!("{{\"event\":\"property-change\"}}\n{}\n", json!({ "request_id": request["request_id"], "error": error, "data": null }));
+                    writer.write_all(reply.as_bytes()).await.unwrap();
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn fade_steps() {
+        let fade = Fade { from: 10, to: 50, over: Duration::from_secs(1) };
+        assert_eq!(vec![20, 30, 40, 50], fade.steps());
+        assert_eq!(vec![80], Fade { from: 10, to: 80, over: Duration::ZERO }.steps());
+        assert_eq!(vec![60, 40], Fade { from: 80, to: 40, over: Duration::from_millis(300) }.steps());
+    }
+
+    #[test]
+    fn talks_to_mpv() {
+        async_std::task::block_on(async {
+            let socket = std::env::temp_dir().join(format!("clockd-fake-mpv-{}.sock", std::process::id()));
+            std::fs::remove_file(&socket).ok();
+            let log = Arc::new(Mutex::new(Vec::new()));
+            // mpv creates its socket a little after starting
+            let listen = {
+                let (socket, log) = (socket.clone(), log.clone());
+                async move {
+                    Timer::after(Duration::from_millis(100)).await;
+                    fake_mpv(UnixListener::bind(&socket).await.unwrap(), log).await;
+                }
+            };
+            spawn(listen);
+            let fade = Fade { from: 10, to: 30, over: Duration::from_millis(500) };
+            fade_in(&socket, fade, Duration::from_secs(2)).await.unwrap();
+            stop(&socket, Duration::from_secs(1)).await.unwrap();
+            let mut ipc = MpvIpc::connect(&socket, Duration::ZERO).await.unwrap();
+            assert!(ipc.command(json!(["bogus"])).await.is_err());
+            let expected = vec![
+                json!(["set_property", "volume", 20]),
+                json!(["set_property", "volume", 30]),
+                json!(["set_property", "pause", true]),
+                json!(["quit"]),
+                json!(["bogus"]),
+            ];
+            assert_eq!(expected, *log.lock().await);
+            std::fs::remove_file(&socket).ok();
+        });
+    }
+}
@@ -0,0 +1,235 @@
+use crate::*;
+use std::collections::HashMap;
+use zbus::zvariant::Value;
+
+#[zbus::dbus_proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+
+    fn close_notification(&self, id: u32) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn action_invoked(&self, id: u32, action_key: String) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn notification_closed(&self, id: u32, reason: u32) -> zbus::Result<()>;
+}
+
+/// Low for silent alarms, normal for sounds played once and critical for
+/// sounds that loop until acknowledged
+fn urgency(alarm: &Alarm) -> u8 {
+    match (alarm.sound_policy, &alarm.soundfile) {
+        (SoundPolicy::Loop, Some(_)) => 2,
+        (_, Some(_)) => 1,
+        (_, None) => 0,
+    }
+}
+
+/// Shows a notification for each ringing alarm, with actions to dismiss
+/// or snooze it
+struct Notifier {
+    proxy: NotificationsProxy<'static>,
+    /// alarms by the id of their notification
+    shown: HashMap<u32, UnixMoment>,
+    snooze: Duration,
+}
+
+enum Input {
+    Event(Box<AppEvent>),
+    Action(u32, String),
+    Closed(u32),
+}
+
+impl Notifier {
+    async fn ring(&mut self, alarm: &Alarm) -> zbus::Result<()> {
+        let snooze = format!("Snooze {}m", self.snooze.as_secs() / 60);
+        let actions = ["dismiss", "Dismiss", "snooze", &snooze];
+        let hints = HashMap::from([("urgency", Value::U8(urgency(alarm)))]);
+        let body = alarm.notes.as_deref().unwrap_or("");
+        let id = self.proxy.notify("clockd", 0, "alarm-clock", &alarm.name, body, &actions, hints, 0).await?;
+        self.shown.insert(id, alarm.creation_t);
+        Ok(())
+    }
+
+    /// Closes the notifications of the alarm, or of all alarms
+    async fn close(&mut self, creation_t: Option<UnixMoment>) -> zbus::Result<()> {
+        let ids: Vec<u32> = self.shown.iter()
+            .filter(|(_, t)| creation_t.map(|c| c == **t).unwrap_or(true))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            self.shown.remove(&id);
+            self.proxy.close_notification(id).await?;
+        }
+        Ok(())
+    }
+
+    /// The command for an action the user picked
+    fn command_for(&mut self, id: u32, action: &str) -> Option<AppCommand> {
+        let creation_t = self.shown.remove(&id)?;
+        match action {
+            "dismiss" => Some(AppCommand::AcknowledgeAlarm(creation_t)),
+            "snooze" => Some(AppCommand::Snooze(creation_t, self.snooze)),
+            _ => None,
+        }
+    }
+}
+
+/// Shows ringing alarms as notifications on `conn` and sends back the
+/// commands of the actions the user picks
+pub async fn run_notifier(conn: zbus::Connection, mut event_rx: broadcast::Receiver<AppEvent>, cmd_tx: Sender<AppCommand>, snooze: Duration) -> zbus::Result<()> {
+    use futures_lite::FutureExt;
+    let proxy = NotificationsProxy::new(&conn).await?;
+    let mut actions = proxy.receive_action_invoked().await?;
+    let mut closed = proxy.receive_notification_closed().await?;
+    let mut n = Notifier { proxy: proxy.clone(), shown: HashMap::new(), snooze };
+    loop {
+        let input = async { event_rx.next().await.map(|ev| Input::Event(Box::new(ev))) }
+            .or(async {
+                let signal = actions.next().await?;
+                let args = signal.args().ok()?;
+                Some(Input::Action(args.id, args.action_key))
+            })
+            .or(async { Some(Input::Closed(closed.next().await?.args().ok()?.id)) })
+            .await;
+        let result = match input {
+            None => return Ok(()),
+            Some(Input::Event(ev)) => match *ev {
+                AppEvent::Ring(alarm) => n.ring(&alarm).await,
+                AppEvent::AckAlarm(t) | AppEvent::Snooze(t) => n.close(Some(t)).await,
+                AppEvent::Ack => n.close(None).await,
+                _ => Ok(()),
+            },
+            Some(Input::Action(id, action)) => {
+                if let Some(cmd) = n.command_for(id, &action) {
+                    cmd_tx.send(cmd).await;
+                }
+                Ok(())
+            },
+            Some(Input::Closed(id)) => {
+                n.shown.remove(&id);
+                Ok(())
+            },
+        };
+        if let Err(e) = result {
+            warn!("notifications: {e}");
+        }
+    }
+}
+
+pub async fn start_notifier(event_rx: broadcast::Receiver<AppEvent>, cmd_tx: Sender<AppCommand>, snooze: Duration) {
+    let result = match zbus::Connection::session().await {
+        Ok(conn) => run_notifier(conn, event_rx, cmd_tx, snooze).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        error!("notifications: {e}");
+    }
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+    use crate::fixture::*;
+    use zbus::zvariant::OwnedValue;
+
+    const PATH: &str = "/org/freedesktop/Notifications";
+
+    /// Records what clients ask of it
+    struct MockServer {
+        next_id: u32,
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[zbus::dbus_interface(name = "org.freedesktop.Notifications")]
+    impl MockServer {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(&mut self, app_name: String, replaces_id: u32, app_icon: String, summary: String, body: String, actions: Vec<String>, hints: HashMap<String, OwnedValue>, expire_timeout: i32) -> u32 {
+            self.next_id += 1;
+            let urgency = hints.get("urgency").and_then(|v| v.downcast_ref::<u8>()).copied().unwrap_or(1);
+            self.log.lock().unwrap().push(format!("notify {summary}: {body} {actions:?} urgency {urgency}"));
+            self.next_id
+        }
+
+        fn close_notification(&mut self, id: u32) {
+            self.log.lock().unwrap().push(format!("close {id}"));
+        }
+
+        #[dbus_interface(signal)]
+        async fn action_invoked(ctxt: &zbus::SignalContext<'_>, id: u32, action_key: &str) -> zbus::Result<()>;
+    }
+
+    async fn wait_for(log: &std::sync::Mutex<Vec<String>>, len: usize) {
+        for _ in 0..100 {
+            if log.lock().unwrap().len() >= len { return; }
+            Timer::after(Duration::from_millis(20)).await;
+        }
+        panic!("mock server got {:?}", log.lock().unwrap());
+    }
+
+    #[test]
+    fn notifies_over_dbus() {
+        let dir = std::env::temp_dir().join(format!("clockd-dbus-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bus = dir.join("bus");
+        let daemon = std::process::Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--nopidfile"])
+            .arg(format!("--address=unix:path={}", bus.display()))
+            .spawn();
+        let Ok(mut daemon) = daemon else {
+            eprintln!("skipping, dbus-daemon is not installed");
+            return;
+        };
+        async_std::task::block_on(async {
+            for _ in 0..100 {
+                if bus.exists() { break; }
+                Timer::after(Duration::from_millis(20)).await;
+            }
+            let address = format!("unix:path={}", bus.display());
+            let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let server = zbus::ConnectionBuilder::address(address.as_str()).unwrap()
+                .name("org.freedesktop.Notifications").unwrap()
+                .serve_at(PATH, MockServer { next_id: 0, log: log.clone() }).unwrap()
+                .build().await.unwrap();
+            let client = zbus::ConnectionBuilder::address(address.as_str()).unwrap().build().await.unwrap();
+            let (event_tx, event_rx) = broadcast::broadcast(4);
+            let (cmd_tx, cmd_rx) = channel::unbounded();
+            spawn(run_notifier(client, event_rx, cmd_tx, Duration::from_secs(300)));
+
+            let mut tea = Alarm::new_from_unixmoment("Tea", Some("/tmp/a.ogg".into()), UnixMoment::new(NOW));
+            tea.sound_policy = SoundPolicy::Loop;
+            tea.notes = Some(String::from("green"));
+            event_tx.broadcast(AppEvent::Ring(Box::new(tea.clone()))).await.unwrap();
+            wait_for(&log, 1).await;
+            assert_eq!("notify Tea: green [\"dismiss\", \"Dismiss\", \"snooze\", \"Snooze 5m\"] urgency 2", log.lock().unwrap()[0]);
+
+            let ctxt = zbus::SignalContext::new(&server, PATH).unwrap();
+            MockServer::action_invoked(&ctxt, 1, "snooze").await.unwrap();
+            let cmd = async_std::future::timeout(Duration::from_secs(2), cmd_rx.recv()).await.unwrap().unwrap();
+            assert!(matches!(cmd, AppCommand::Snooze(t, d) if t == tea.creation_t && d == Duration::from_secs(300)));
+
+            event_tx.broadcast(AppEvent::Ring(Box::new(tea))).await.unwrap();
+            event_tx.broadcast(AppEvent::Ack).await.unwrap();
+            wait_for(&log, 3).await;
+            assert_eq!("close 2", log.lock().unwrap()[2]);
+        });
+        daemon.kill().ok();
+        daemon.wait().ok();
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
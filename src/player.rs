@@ -0,0 +1,183 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Built in player templates, in the order they are probed
+pub const PLAYER_PRESETS: [(&str, &str); 5] = [
+    ("mpv", "mpv --no-terminal --no-video --input-ipc-server={ipc} --volume={volume} --loop-file={repeats} {file}"),
+    ("pw-play", "pw-play --volume={volume_frac} {file}"),
+    ("paplay", "paplay --volume={volume_pa} {file}"),
+    ("ffplay", "ffplay -nodisp -autoexit -loglevel error -volume {volume} -loop {loops} {file}"),
+    ("aplay", "aplay -q {file}"),
+];
+
+const PLACEHOLDERS: [&str; 7] = ["file", "volume", "volume_frac", "volume_pa", "loops", "repeats", "ipc"];
+
+/// Command line of an audio player with placeholders:
+/// {file}, {volume} (0-100), {volume_frac} (0.0-1.0), {volume_pa} (0-65536),
+/// {loops} (times to play), {repeats} (loops - 1) and {ipc}, the path of a
+/// socket for mpv's JSON IPC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerTemplate {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum PlayerError {
+    Empty,
+    UnbalancedQuote,
+    UnknownPlaceholder(String),
+    MissingFile,
+    /// the program is not on PATH
+    NotFound(String),
+    /// no preset player is installed
+    NoneFound,
+}
+impl std::error::Error for PlayerError {}
+impl std::fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayerError::Empty => write!(f, "Player command is empty"),
+            PlayerError::UnbalancedQuote => write!(f, "Player command has an unbalanced quote"),
+            PlayerError::UnknownPlaceholder(p) => write!(f, "Unknown placeholder {{{p}}} in player command"),
+            PlayerError::MissingFile => write!(f, "Player command has no {{file}} placeholder"),
+            PlayerError::NotFound(p) => write!(f, "Audio player {p} was not found"),
+            PlayerError::NoneFound => {
+                let names: Vec<&str> = PLAYER_PRESETS.iter().map(|(n, _)| *n).collect();
+                write!(f, "No audio player found, install one of {} or set --player", names.join(", "))
+            },
+        }
+    }
+}
+
+/// Splits on whitespace, keeping single or double quoted parts together
+pub(crate) fn split_words(s: &str) -> Result<Vec<String>, PlayerError> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in s.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            },
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() { return Err(PlayerError::UnbalancedQuote); }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Names of the placeholders in a word, e.g. "volume" for "--volume={volume}"
+fn placeholders(word: &str) -> impl Iterator<Item = &str> {
+    word.split('{').skip(1).filter_map(|rest| rest.split_once('}')).map(|(name, _)| name)
+}
+
+impl PlayerTemplate {
+    pub fn parse(s: &str) -> Result<Self, PlayerError> {
+        let mut words = split_words(s)?.into_iter();
+        let program = words.next().ok_or(PlayerError::Empty)?;
+        let args: Vec<String> = words.collect();
+        for name in args.iter().flat_map(|a| placeholders(a)) {
+            if ! PLACEHOLDERS.contains(&name) {
+                return Err(PlayerError::UnknownPlaceholder(name.to_owned()));
+            }
+        }
+        if ! args.iter().any(|a| a.contains("{file}")) {
+            return Err(PlayerError::MissingFile);
+        }
+        Ok(Self { program, args })
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        PLAYER_PRESETS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, t)| Self::parse(t).expect("bad preset"))
+    }
+
+    /// A preset name or a template
+    pub fn from_config(s: &str) -> Result<Self, PlayerError> {
+        Self::preset(s).map(Ok).unwrap_or_else(|| Self::parse(s))
+    }
+
+    /// The first preset whose program is on `path`
+    pub fn probe(path: Option<&OsStr>) -> Result<Self, PlayerError> {
+        PLAYER_PRESETS.iter()
+            .find(|(name, _)| find_program(name, path).is_some())
+            .map(|(_, t)| Self::parse(t).expect("bad preset"))
+            .ok_or(PlayerError::NoneFound)
+    }
+
+    /// Full path of the program, looked up on `path` unless it has a slash
+    pub fn locate(&self, path: Option<&OsStr>) -> Result<PathBuf, PlayerError> {
+        find_program(&self.program, path).ok_or_else(|| PlayerError::NotFound(self.program.clone()))
+    }
+
+    /// The player can be controlled over mpv's JSON IPC
+    pub fn uses_ipc(&self) -> bool {
+        self.args.iter().any(|a| a.contains("{ipc}"))
+    }
+
+    /// Arguments with the placeholders filled in. `volume` is in percent.
+    /// Without an IPC socket {ipc} is left empty, which turns it off in mpv.
+    pub fn args_for(&self, file: &Path, volume: u8, loops: u32, ipc: Option<&Path>) -> Vec<String> {
+        let volume = volume.min(100);
+        let values = [
+            ("{file}", file.display().to_string()),
+            ("{volume}", volume.to_string()),
+            ("{volume_frac}", format!("{:.2}", volume as f64 / 100.0)),
+            ("{volume_pa}", (volume as u32 * 65536 / 100).to_string()),
+            ("{loops}", loops.max(1).to_string()),
+            ("{repeats}", (loops.max(1) - 1).to_string()),
+            ("{ipc}", ipc.map(|p| p.display().to_string()).unwrap_or_default()),
+        ];
+        self.args.iter().map(|arg| {
+            values.iter().fold(arg.clone(), |a, (key, value)| a.replace(key, value))
+        }).collect()
+    }
+}
+
+impl std::fmt::Display for PlayerTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.program, self.args.join(" "))
+    }
+}
+
+pub(crate) fn find_program(program: &str, path: Option<&OsStr>) -> Option<PathBuf> {
+    let is_file = |p: &Path| p.metadata().map(|m| m.is_file()).unwrap_or(false);
+    if program.contains('/') {
+        let p = PathBuf::from(program);
+        return is_file(&p).then_some(p);
+    }
+    std::env::split_paths(path?).map(|dir| dir.join(program)).find(|p| is_file(p))
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+
+    #[test]
+    fn fills_placeholders() {
+        let mpv = PlayerTemplate::preset("mpv").unwrap();
+        let args = mpv.args_for(Path::new("/tmp/a b.ogg"), 40, 3, Some(Path::new("/run/mpv.sock")));
+        assert_eq!(vec!["--no-terminal", "--no-video", "--input-ipc-server=/run/mpv.sock", "--volume=40", "--loop-file=2", "/tmp/a b.ogg"], args);
+        assert!(mpv.uses_ipc());
+        let paplay = PlayerTemplate::from_config("paplay").unwrap();
+        assert_eq!(vec!["--volume=26214", "/x"], paplay.args_for(Path::new("/x"), 40, 1, None));
+        let custom = PlayerTemplate::from_config("'/opt/my player' -v {volume_frac} \"{file}\"").unwrap();
+        assert_eq!("/opt/my player", custom.program);
+        assert_eq!(vec!["-v", "0.40", "/x"], custom.args_for(Path::new("/x"), 40, 1, None));
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!(matches!(PlayerTemplate::parse("play {speed} {file}"), Err(PlayerError::UnknownPlaceholder(_))));
+        assert!(matches!(PlayerTemplate::parse("play"), Err(PlayerError::MissingFile)));
+        assert!(matches!(PlayerTemplate::parse("play '{file}"), Err(PlayerError::UnbalancedQuote)));
+        assert!(matches!(PlayerTemplate::probe(Some(OsStr::new("/nonexistent"))), Err(PlayerError::NoneFound)));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::*;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use std::path::PathBuf;
+
+/// A time as written in records: unix seconds or a time expression
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RecordTime {
+    Unix(i64),
+    Expr(String),
+}
+
+impl std::str::FromStr for RecordTime {
+    type Err = std::convert::Infallible;
+
+    /// all digits is unix seconds, anything else an expression
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(match s.parse() {
+            Ok(t) if s.bytes().all(|b| b.is_ascii_digit()) => Self::Unix(t),
+            _ => Self::Expr(s.to_owned()),
+        })
+    }
+}
+
+impl std::fmt::Display for RecordTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordTime::Unix(t) => write!(f, "{t}"),
+            RecordTime::Expr(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Flat description of an alarm, for exporting and importing alarms as
+/// JSON lines or CSV
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlarmRecord {
+    pub name: String,
+    pub time: RecordTime,
+    #[serde(default)]
+    pub soundfile: Option<PathBuf>,
+    /// cron or OnCalendar schedule
+    #[serde(default)]
+    pub repeat: Option<String>,
+    /// resolve the time expression again each day
+    #[serde(default)]
+    pub daily: bool,
+    #[serde(default)]
+    pub workdays_only: bool,
+    #[serde(default)]
+    pub uid: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub sound_selection: SoundSelection,
+    #[serde(default)]
+    pub sound_policy: SoundPolicy,
+}
+
+/// A pending or ringing alarm as the daemon reports it for AppCommand::List
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingAlarm {
+    /// creation time, which identifies the alarm for AppCommand::Remove
+    /// and AppCommand::AcknowledgeAlarm
+    pub id: UnixMoment,
+    /// next time it goes off
+    pub end_t: UnixMoment,
+    pub managed: bool,
+    #[serde(default)]
+    pub ringing: bool,
+    #[serde(flatten)]
+    pub record: AlarmRecord,
+}
+
+impl PendingAlarm {
+    pub fn from_alarm(a: &Alarm) -> Self {
+        Self { id: a.creation_t, end_t: a.end_t, managed: a.managed, ringing: false, record: AlarmRecord::from_alarm(a) }
+    }
+}
+
+/// columns of the CSV format, in order
+pub const RECORD_CSV_HEADER: &str = "name,time,soundfile,repeat,daily,workdays_only,uid,notes,tags,sound_selection,sound_policy";
+
+#[derive(Debug)]
+pub enum RecordError {
+    Json(serde_json::Error),
+    /// wrong number of CSV fields, or a field that could not be read
+    Csv(String),
+    BadRepeat(String),
+    /// "daily" needs a time expression, not a fixed time
+    DailyWithoutExpr,
+}
+impl std::error::Error for RecordError {}
+impl std::fmt::Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordError::Json(e) => write!(f, "Bad JSON record: {e}"),
+            RecordError::Csv(msg) => write!(f, "Bad CSV record: {msg}"),
+            RecordError::BadRepeat(s) => write!(f, "Not a cron or OnCalendar schedule: {s}"),
+            RecordError::DailyWithoutExpr => write!(f, "A daily alarm needs a time expression"),
+        }
+    }
+}
+
+/// Splits a CSV line, with double quotes around fields that contain commas
+/// and "" for a quote inside them
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            },
+            '"' => quoted = ! quoted,
+            ',' if ! quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// An enum field written the same as in JSON, e.g. "RoundRobin"
+fn enum_from_csv<T: DeserializeOwned + Default>(field: Option<&str>) -> Result<T, RecordError> {
+    match field {
+        None => Ok(T::default()),
+        Some(f) => serde_json::from_value(serde_json::Value::String(f.to_owned()))
+            .map_err(|_| RecordError::Csv(format!("unknown value {f}"))),
+    }
+}
+
+fn enum_to_csv<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+fn quote_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+impl AlarmRecord {
+    pub fn from_alarm(a: &Alarm) -> Self {
+        let time = match a.daily {
+            Some(ref expr) => RecordTime::Expr(expr.clone()),
+            None => RecordTime::Unix(a.end_t.seconds()),
+        };
+        Self {
+            name: a.name.clone(),
+            time,
+            soundfile: a.soundfile.clone(),
+            repeat: a.recurrence.as_ref().map(Schedule::to_string),
+            daily: a.daily.is_some(),
+            workdays_only: a.workdays_only,
+            uid: a.uid.clone(),
+            notes: a.notes.clone(),
+            tags: a.tags.clone(),
+            sound_selection: a.sound_selection,
+            sound_policy: a.sound_policy,
+        }
+    }
+
+    pub fn from_json(line: &str) -> Result<Self, RecordError> {
+        serde_json::from_str(line).map_err(RecordError::Json)
+    }
+
+    /// Reads a line in the RECORD_CSV_HEADER layout, with tags separated by
+    /// spaces. Trailing columns may be left out.
+    pub fn from_csv(line: &str) -> Result<Self, RecordError> {
+        let fields = split_csv(line);
+        if fields.len() < 2 || fields.len() > 11 {
+            return Err(RecordError::Csv(format!("expected 2 to 11 fields, got {}", fields.len())));
+        }
+        let field = |i: usize| fields.get(i).map(|f| f.trim()).filter(|f| ! f.is_empty());
+        let flag = |i: usize| match field(i) {
+            None | Some("false") | Some("0") => Ok(false),
+            Some("true") | Some("1") => Ok(true),
+            Some(other) => Err(RecordError::Csv(format!("expected true or false, got {other}"))),
+        };
+        let name = field(0).ok_or(RecordError::Csv(String::from("missing name")))?;
+        let time = field(1).ok_or(RecordError::Csv(String::from("missing time")))?;
+        Ok(Self {
+            name: name.to_owned(),
+            time: time.parse().unwrap_or_else(|e| match e {}),
+            soundfile: field(2).map(PathBuf::from),
+            repeat: field(3).map(str::to_owned),
+            daily: flag(4)?,
+            workdays_only: flag(5)?,
+            uid: field(6).map(str::to_owned),
+            notes: field(7).map(str::to_owned),
+            tags: field(8).map(|t| t.split_whitespace().map(str::to_owned).collect()).unwrap_or_default(),
+            sound_selection: enum_from_csv(field(9))?,
+            sound_policy: enum_from_csv(field(10))?,
+        })
+    }
+
+    pub fn to_csv(&self) -> String {
+        let soundfile = self.soundfile.as_ref().map(|p| p.display().to_string()).unwrap_or_default();
+        [
+            self.name.clone(),
+            self.time.to_string(),
+            soundfile,
+            self.repeat.clone().unwrap_or_default(),
+            self.daily.to_string(),
+            self.workdays_only.to_string(),
+            self.uid.clone().unwrap_or_default(),
+            self.notes.clone().unwrap_or_default(),
+            self.tags.join(" "),
+            enum_to_csv(&self.sound_selection),
+            enum_to_csv(&self.sound_policy),
+        ].iter().map(|f| quote_csv(f)).collect::<Vec<_>>().join(",")
+    }
+
+    pub fn into_spec(self) -> Result<AlarmSpec, RecordError> {
+        let mut spec = match self.time {
+            RecordTime::Unix(t) => AlarmSpec::new(self.name, self.soundfile, UnixMoment::new(t)),
+            RecordTime::Expr(ref e) => AlarmSpec::from_expr(self.name, self.soundfile, e.clone()),
+        };
+        if let Some(r) = self.repeat {
+            let schedule = Schedule::parse(&r).map_err(|_| RecordError::BadRepeat(r))?;
+            spec = spec.with_recurrence(schedule);
+        }
+        if self.daily {
+            let RecordTime::Expr(e) = self.time else { return Err(RecordError::DailyWithoutExpr) };
+            spec = spec.repeat_daily(e);
+        }
+        if self.workdays_only {
+            spec = spec.workdays_only();
+        }
+        if let Some(uid) = self.uid {
+            spec = spec.with_uid(uid);
+        }
+        if let Some(notes) = self.notes {
+            spec = spec.with_notes(notes);
+        }
+        if ! self.tags.is_empty() {
+            spec = spec.with_tags(self.tags);
+        }
+        if self.sound_policy == SoundPolicy::Loop {
+            spec = spec.loop_sound();
+        }
+        Ok(spec.with_sound_selection(self.sound_selection))
+    }
+}
+
+/// Reads JSON lines or CSV, whichever the first record looks like. Blank
+/// lines, "#" comments and a CSV header are skipped. Returns each record
+/// with its line number.
+pub fn parse_records(text: &str) -> Vec<(usize, Result<AlarmRecord, RecordError>)> {
+    let mut lines = text.lines()
+        .enumerate()
+        .map(|(n, line)| (n + 1, line.trim()))
+        .filter(|(_, line)| ! line.is_empty() && ! line.starts_with('#'))
+        .peekable();
+    let json = matches!(lines.peek(), Some((_, line)) if line.starts_with('{'));
+    if ! json && matches!(lines.peek(), Some((_, line)) if line.starts_with("name,")) {
+        lines.next();
+    }
+    lines.map(|(n, line)| {
+        let record = if json { AlarmRecord::from_json(line) } else { AlarmRecord::from_csv(line) };
+        (n, record)
+    }).collect()
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+    use crate::fixture::*;
+
+    #[test]
+    fn csv_round_trip() {
+        let line = "\"Class, \"\"maths\"\"\",mon 8:00,,30 8 * * 1,false,false,,bring a calculator,school maths,RoundRobin,Loop";
+        let r = AlarmRecord::from_csv(line).unwrap();
+        assert_eq!("Class, \"maths\"", r.name);
+        assert_eq!(RecordTime::Expr(String::from("mon 8:00")), r.time);
+        assert_eq!(Some(String::from("30 8 * * 1")), r.repeat);
+        assert_eq!(Some(String::from("bring a calculator")), r.notes);
+        assert_eq!(vec!["school", "maths"], r.tags);
+        assert_eq!(SoundSelection::RoundRobin, r.sound_selection);
+        assert_eq!(SoundPolicy::Loop, r.sound_policy);
+        assert_eq!(line, r.to_csv());
+        let r = AlarmRecord::from_csv("tea,1709744400").unwrap();
+        assert_eq!(RecordTime::Unix(1709744400), r.time);
+        assert_eq!(SoundSelection::Random, r.sound_selection);
+        assert_eq!(SoundPolicy::Once, r.sound_policy);
+        assert!(AlarmRecord::from_csv("tea,1709744400,,,,,,,,,Forever").is_err());
+        assert!(AlarmRecord::from_csv("tea").is_err());
+    }
+
+    #[test]
+    fn json_accepts_both_times() {
+        let r = AlarmRecord::from_json(r#"{"name": "tea", "time": 1709744400}"#).unwrap();
+        assert_eq!(RecordTime::Unix(1709744400), r.time);
+        let r = AlarmRecord::from_json(r#"{"name": "tea", "time": "in 5m", "daily": true}"#).unwrap();
+        assert!(r.into_spec().is_ok());
+        let r = AlarmRecord::from_json(r#"{"name": "tea", "time": 1709744400, "daily": true}"#).unwrap();
+        assert!(matches!(r.into_spec(), Err(RecordError::DailyWithoutExpr)));
+        let r = AlarmRecord::from_json(r#"{"name": "tea", "time": "in 5m", "sound_selection": "RoundRobin", "sound_policy": "Loop"}"#).unwrap();
+        let alarm = r.into_spec().unwrap().into_alarm(UnixMoment::new(NOW), eastern().as_ref(), ResolveOptions::default()).unwrap();
+        assert_eq!(SoundSelection::RoundRobin, alarm.sound_selection);
+        assert_eq!(SoundPolicy::Loop, alarm.sound_policy);
+        let r = AlarmRecord::from_alarm(&alarm);
+        assert_eq!((SoundSelection::RoundRobin, SoundPolicy::Loop), (r.sound_selection, r.sound_policy));
+    }
+
+    #[test]
+    fn detects_format() {
+        let csv = format!("{RECORD_CSV_HEADER}\n# monday\nMaths,mon 8:00\n\nArt,mon 9:00,,,maybe\n");
+        let records = parse_records(&csv);
+        assert_eq!(3, records[0].0);
+        assert_eq!("Maths", records[0].1.as_ref().unwrap().name);
+        assert_eq!(5, records[1].0);
+        assert!(records[1].1.is_err());
+        let json = "{\"name\": \"tea\", \"time\": \"in 5m\"}\ntea,in 5m\n";
+        let records = parse_records(json);
+        assert!(records[0].1.is_ok() && records[1].1.is_err());
+    }
+}
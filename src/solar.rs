@@ -0,0 +1,117 @@
+use crate::*;
+
+/// Where the sun is observed from, in degrees. Longitude is positive east.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl std::str::FromStr for Location {
+    type Err = String;
+
+    /// accepts "latitude,longitude", e.g. "52.52,13.40"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("expected latitude,longitude in degrees, got {s}");
+        let (lat, lon) = s.split_once(',').ok_or_else(bad)?;
+        let latitude: f64 = lat.trim().parse().map_err(|_| bad())?;
+        let longitude: f64 = lon.trim().parse().map_err(|_| bad())?;
+        if ! (-90.0..=90.0).contains(&latitude) || ! (-180.0..=180.0).contains(&longitude) {
+            return Err(bad());
+        }
+        Ok(Self { latitude, longitude })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolarEvent {
+    Sunrise,
+    Sunset,
+    CivilDawn,
+    CivilDusk,
+    NauticalDawn,
+    NauticalDusk,
+    AstronomicalDawn,
+    AstronomicalDusk,
+}
+
+impl SolarEvent {
+    /// altitude of the sun's centre at the event, in degrees
+    fn altitude(&self) -> f64 {
+        match self {
+            // allows for refraction and the radius of the sun
+            SolarEvent::Sunrise | SolarEvent::Sunset => -0.833,
+            SolarEvent::CivilDawn | SolarEvent::CivilDusk => -6.0,
+            SolarEvent::NauticalDawn | SolarEvent::NauticalDusk => -12.0,
+            SolarEvent::AstronomicalDawn | SolarEvent::AstronomicalDusk => -18.0,
+        }
+    }
+    fn is_morning(&self) -> bool {
+        matches!(self, SolarEvent::Sunrise | SolarEvent::CivilDawn | SolarEvent::NauticalDawn | SolarEvent::AstronomicalDawn)
+    }
+
+    /// Unix time of the event on the given day, using the sunrise equation.
+    /// Accurate to about a minute away from the poles. Returns None if the
+    /// sun does not reach the event's altitude that day.
+    pub fn time_on(&self, date: Date, location: Location) -> Option<i64> {
+        let (year, month, day) = date;
+        let noon = tz::UtcDateTime::new(year, month, day, 12, 0, 0, 0).ok()?;
+        // days since 2000-01-01 12:00 UTC, shifted to solar noon at the longitude
+        let n = (noon.unix_time() - 946728000) / 86400;
+        let j = n as f64 - location.longitude / 360.0;
+        let m = (357.5291 + 0.98560028 * j).rem_euclid(360.0).to_radians();
+        let c = 1.9148 * m.sin() + 0.02 * (2.0 * m).sin() + 0.0003 * (3.0 * m).sin();
+        let lambda = (m.to_degrees() + c + 180.0 + 102.9372).rem_euclid(360.0).to_radians();
+        let transit = j + 0.0053 * m.sin() - 0.0069 * (2.0 * lambda).sin();
+        let declination = (lambda.sin() * 23.4397f64.to_radians().sin()).asin();
+        let phi = location.latitude.to_radians();
+        let cos_w = (self.altitude().to_radians().sin() - phi.sin() * declination.sin())
+            / (phi.cos() * declination.cos());
+        if ! (-1.0..=1.0).contains(&cos_w) { return None; }
+        let w = cos_w.acos().to_degrees() / 360.0;
+        let t = if self.is_morning() { transit - w } else { transit + w };
+        Some(946728000 + (t * 86400.0).round() as i64)
+    }
+}
+
+impl std::fmt::Display for SolarEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            SolarEvent::Sunrise => "sunrise",
+            SolarEvent::Sunset => "sunset",
+            SolarEvent::CivilDawn => "civil dawn",
+            SolarEvent::CivilDusk => "civil dusk",
+            SolarEvent::NauticalDawn => "nautical dawn",
+            SolarEvent::NauticalDusk => "nautical dusk",
+            SolarEvent::AstronomicalDawn => "astronomical dawn",
+            SolarEvent::AstronomicalDusk => "astronomical dusk",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+
+    const NEW_YORK: Location = Location { latitude: 40.7128, longitude: -74.0060 };
+
+    #[test]
+    fn new_york_in_march() {
+        // 2024-03-06: sunrise 06:22 EST, sunset 17:53 EST
+        let rise = SolarEvent::Sunrise.time_on((2024, 3, 6), NEW_YORK).unwrap();
+        assert!((rise - 1709724120).abs() < 120, "{rise}");
+        let set = SolarEvent::Sunset.time_on((2024, 3, 6), NEW_YORK).unwrap();
+        assert!((set - 1709765580).abs() < 120, "{set}");
+        let dawn = SolarEvent::CivilDawn.time_on((2024, 3, 6), NEW_YORK).unwrap();
+        assert!(dawn < rise - 20 * 60);
+    }
+
+    #[test]
+    fn polar_night() {
+        let tromso = Location { latitude: 69.65, longitude: 18.96 };
+        assert_eq!(None, SolarEvent::Sunrise.time_on((2024, 12, 21), tromso));
+        assert!("69.65,18.96".parse::<Location>().is_ok());
+        assert!("91,0".parse::<Location>().is_err());
+    }
+}
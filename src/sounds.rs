@@ -0,0 +1,228 @@
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+/// How a file is picked when an alarm's sound is a directory, playlist or glob
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum SoundSelection {
+    #[default]
+    Random,
+    /// each file in turn, one per ring
+    RoundRobin,
+}
+
+/// Directory of sounds that alarms can refer to by name, e.g. "chime" for
+/// "chime.ogg"
+#[derive(Debug, Clone)]
+pub struct SoundLibrary {
+    pub dir: PathBuf,
+    /// played by alarms without a sound
+    pub default: Option<String>,
+    /// played when an alarm's sound cannot be found
+    pub fallback: Option<String>,
+}
+
+/// A sound file name or path. Names have no slash.
+fn is_name(sound: &Path) -> bool {
+    ! sound.as_os_str().as_encoded_bytes().contains(&b'/')
+}
+
+/// The file name has * or ? wildcards, e.g. "~/sounds/birds-*.ogg"
+pub fn is_glob(sound: &Path) -> bool {
+    sound.file_name().map(|n| n.as_encoded_bytes().iter().any(|b| *b == b'*' || *b == b'?')).unwrap_or(false)
+}
+
+/// Matches * and ? wildcards
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => wildcard_match(rest, name) || (! name.is_empty() && wildcard_match(pattern, &name[1..])),
+        (Some((b'?', rest)), Some((_, name_rest))) => wildcard_match(rest, name_rest),
+        (Some((p, rest)), Some((n, name_rest))) if p == n => wildcard_match(rest, name_rest),
+        _ => false,
+    }
+}
+
+fn is_playlist(sound: &Path) -> bool {
+    matches!(sound.extension(), Some(e) if e == "m3u" || e == "m3u8")
+}
+
+/// Files of a directory, without hidden ones, sorted
+fn files_in(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    let mut files: Vec<PathBuf> = entries.flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && ! p.file_name().map(|n| n.as_encoded_bytes().starts_with(b".")).unwrap_or(true))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Entries of an m3u playlist, relative ones from the playlist's directory
+fn read_playlist(playlist: &Path) -> Vec<PathBuf> {
+    let Ok(text) = std::fs::read_to_string(playlist) else { return Vec::new() };
+    let base = playlist.parent().unwrap_or(Path::new("."));
+    text.lines()
+        .map(str::trim)
+        .filter(|l| ! l.is_empty() && ! l.starts_with('#'))
+        .map(|l| base.join(l))
+        .filter(|p| p.is_file())
+        .collect()
+}
+
+/// The files a sound stands for: itself, the files of a directory, the
+/// entries of a playlist or the matches of a glob
+pub fn sound_files(sound: &Path) -> Vec<PathBuf> {
+    if is_glob(sound) {
+        let dir = sound.parent().filter(|d| ! d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let pattern = sound.file_name().unwrap().as_encoded_bytes();
+        files_in(dir).into_iter()
+            .filter(|f| f.file_name().map(|n| wildcard_match(pattern, n.as_encoded_bytes())).unwrap_or(false))
+            .collect()
+    } else if sound.is_dir() {
+        files_in(sound)
+    } else if sound.is_file() && is_playlist(sound) {
+        read_playlist(sound)
+    } else if sound.is_file() {
+        vec![sound.to_owned()]
+    } else {
+        Vec::new()
+    }
+}
+
+impl SoundLibrary {
+    /// $XDG_DATA_HOME/clockd/sounds, or ~/.local/share/clockd/sounds
+    pub fn default_dir() -> Option<PathBuf> {
+        let data = std::env::var_os("XDG_DATA_HOME")
+            .filter(|d| ! d.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))?;
+        Some(data.join("clockd/sounds"))
+    }
+
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir, default: None, fallback: None }
+    }
+
+    /// Names and paths of the sounds and sound directories, sorted by name
+    pub fn list(&self) -> Vec<(String, PathBuf)> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else { return Vec::new() };
+        let mut sounds: Vec<(String, PathBuf)> = entries.flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file() || p.is_dir())
+            .filter_map(|p| {
+                let name = p.file_stem()?.to_str()?.to_owned();
+                (! name.starts_with('.')).then_some((name, p))
+            })
+            .collect();
+        sounds.sort();
+        sounds
+    }
+
+    /// The sound with this name, with or without its extension. Names can
+    /// also be directories, playlists or globs in the library.
+    pub fn find(&self, name: &str) -> Option<PathBuf> {
+        let exact = self.dir.join(name);
+        if exact.exists() || is_glob(&exact) { return Some(exact); }
+        self.list().into_iter().find(|(n, _)| n == name).map(|(_, p)| p)
+    }
+
+    /// The file, directory, playlist or glob for a sound name or path, if
+    /// it has any files
+    pub fn lookup(&self, sound: &Path) -> Option<PathBuf> {
+        let found = self.locate(sound)?;
+        (! sound_files(&found).is_empty()).then_some(found)
+    }
+
+    /// The path a sound name stands for, or the path itself
+    fn locate(&self, sound: &Path) -> Option<PathBuf> {
+        if is_name(sound) { self.find(sound.to_str()?) } else { Some(sound.to_owned()) }
+    }
+
+    /// Picks one of the files a sound stands for, `turn` counts the rings
+    /// for SoundSelection::RoundRobin
+    fn pick(&self, sound: &Path, selection: SoundSelection, turn: usize) -> Option<PathBuf> {
+        // read the files once, they may go away between two reads
+        let files = sound_files(&self.locate(sound)?);
+        if files.is_empty() {
+            return None;
+        }
+        let i = match selection {
+            SoundSelection::Random => fastrand::usize(..files.len()),
+            SoundSelection::RoundRobin => turn % files.len(),
+        };
+        files.into_iter().nth(i)
+    }
+
+    /// The file to play for an alarm's sound: one of the sound's files, the
+    /// fallback if they have gone missing, or the default if it has none.
+    /// Also returns a warning when the sound is missing.
+    pub fn sound_for(&self, sound: Option<&Path>, selection: SoundSelection, turn: usize) -> (Option<PathBuf>, Option<String>) {
+        let default = || self.default.as_deref().and_then(|d| self.pick(d.as_ref(), selection, turn));
+        let Some(sound) = sound else { return (default(), None) };
+        if let Some(p) = self.pick(sound, selection, turn) {
+            return (Some(p), None);
+        }
+        let fallback = self.fallback.as_deref().and_then(|f| self.pick(f.as_ref(), selection, turn)).or_else(default);
+        let warning = match fallback {
+            Some(ref p) => format!("sound {sound:?} not found, playing {p:?} instead"),
+            None => format!("sound {sound:?} not found and there is no fallback sound"),
+        };
+        (fallback, Some(warning))
+    }
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+
+    #[test]
+    fn finds_sounds() {
+        let dir = std::env::temp_dir().join(format!("clockd-sounds-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for f in ["chime.ogg", "bell.wav", ".hidden.ogg"] {
+            std::fs::write(dir.join(f), b"").unwrap();
+        }
+        let mut lib = SoundLibrary::new(dir.clone());
+        let names: Vec<String> = lib.list().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(vec!["bell", "chime"], names);
+        assert_eq!(Some(dir.join("chime.ogg")), lib.lookup("chime".as_ref()));
+        assert_eq!(Some(dir.join("bell.wav")), lib.lookup("bell.wav".as_ref()));
+        assert_eq!(None, lib.lookup("/nonexistent/chime.ogg".as_ref()));
+        assert_eq!((None, None), lib.sound_for(None, SoundSelection::Random, 0));
+        lib.default = Some(String::from("bell"));
+        assert_eq!((Some(dir.join("bell.wav")), None), lib.sound_for(None, SoundSelection::Random, 0));
+        // without a fallback, missing sounds play the default
+        let (sound, warning) = lib.sound_for(Some("gone".as_ref()), SoundSelection::Random, 0);
+        assert_eq!(Some(dir.join("bell.wav")), sound);
+        assert!(warning.is_some());
+        lib.fallback = Some(String::from("chime"));
+        assert_eq!(Some(dir.join("chime.ogg")), lib.sound_for(Some("/moved/away.ogg".as_ref()), SoundSelection::Random, 0).0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn picks_from_many() {
+        let dir = std::env::temp_dir().join(format!("clockd-picks-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("birds")).unwrap();
+        for f in ["birds/a.ogg", "birds/b.ogg", "birds/c.wav", "bell.ogg"] {
+            std::fs::write(dir.join(f), b"").unwrap();
+        }
+        std::fs::write(dir.join("mix.m3u"), "#EXTM3U\nbirds/c.wav\n\nbell.ogg\nmissing.ogg\n").unwrap();
+        let lib = SoundLibrary::new(dir.clone());
+        let pick = |sound: &str, turn| lib.sound_for(Some(sound.as_ref()), SoundSelection::RoundRobin, turn).0.unwrap();
+        assert_eq!(dir.join("birds/a.ogg"), pick("birds", 0));
+        assert_eq!(dir.join("birds/a.ogg"), pick("birds", 3));
+        assert_eq!(dir.join("bell.ogg"), pick("mix.m3u", 1));
+        assert_eq!(dir.join("birds/b.ogg"), pick(&format!("{}/birds/*.ogg", dir.display()), 1));
+        assert_eq!(dir.join("birds/a.ogg"), pick(&format!("{}/birds/*.ogg", dir.display()), 2));
+        assert_eq!(2, sound_files(&dir.join("mix.m3u")).len());
+        let (random, _) = lib.sound_for(Some("birds".as_ref()), SoundSelection::Random, 0);
+        assert!(random.unwrap().starts_with(dir.join("birds")));
+        // an empty directory or glob has nothing to pick from
+        std::fs::create_dir_all(dir.join("quiet")).unwrap();
+        assert_eq!(None, lib.pick(&dir.join("quiet"), SoundSelection::RoundRobin, 1));
+        assert_eq!(None, lib.pick(&dir.join("*.flac"), SoundSelection::Random, 0));
+        assert!(wildcard_match(b"a?c*", b"abcdef") && ! wildcard_match(b"*.ogg", b"a.wav"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use crate::*;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+/// Built in speech commands, in the order they are probed. Commands
+/// without {text} read the text on stdin.
+pub const SPEECH_PRESETS: [(&str, &str); 4] = [
+    ("espeak-ng", "espeak-ng -- {text}"),
+    ("espeak", "espeak -- {text}"),
+    ("spd-say", "spd-say --wait {text}"),
+    ("festival", "festival --tts"),
+];
+
+/// Command line of a text to speech program, with the text in place of
+/// {text} or, without it, on stdin. Piper needs a voice and a player, e.g.
+/// "sh -c 'piper -m voice.onnx --output-raw | aplay -q -r 22050 -f S16_LE -t raw -'"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpeechTemplate {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl SpeechTemplate {
+    pub fn parse(s: &str) -> Result<Self, PlayerError> {
+        let mut words = split_words(s)?.into_iter();
+        let program = words.next().ok_or(PlayerError::Empty)?;
+        let args: Vec<String> = words.collect();
+        let unknown = args.iter()
+            .flat_map(|a| a.split('{').skip(1).filter_map(|rest| rest.split_once('}')))
+            .find(|(name, _)| *name != "text");
+        if let Some((name, _)) = unknown {
+            return Err(PlayerError::UnknownPlaceholder(name.to_owned()));
+        }
+        Ok(Self { program, args })
+    }
+
+    /// A preset name or a template
+    pub fn from_config(s: &str) -> Result<Self, PlayerError> {
+        SPEECH_PRESETS.iter()
+            .find(|(n, _)| *n == s)
+            .map(|(_, t)| Self::parse(t))
+            .unwrap_or_else(|| Self::parse(s))
+    }
+
+    /// The first preset whose program is on `path`
+    pub fn probe(path: Option<&OsStr>) -> Result<Self, PlayerError> {
+        SPEECH_PRESETS.iter()
+            .find(|(name, _)| find_program(name, path).is_some())
+            .map(|(_, t)| Self::parse(t).expect("bad preset"))
+            .ok_or(PlayerError::NoneFound)
+    }
+
+    /// Full path of the program, looked up on `path` unless it has a slash
+    pub fn locate(&self, path: Option<&OsStr>) -> Result<PathBuf, PlayerError> {
+        find_program(&self.program, path).ok_or_else(|| PlayerError::NotFound(self.program.clone()))
+    }
+
+    /// The text goes to the program's stdin
+    pub fn text_on_stdin(&self) -> bool {
+        ! self.args.iter().any(|a| a.contains("{text}"))
+    }
+
+    pub fn args_for(&self, text: &str) -> Vec<String> {
+        self.args.iter().map(|a| a.replace("{text}", text)).collect()
+    }
+}
+
+impl std::fmt::Display for SpeechTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.program, self.args.join(" "))
+    }
+}
+
+/// How long until something, the way it is said, e.g. "1 hour 5 minutes"
+pub fn spoken_duration(seconds: i64) -> String {
+    let minutes = (seconds.max(0) + 30) / 60;
+    let plural = |n: i64, unit: &str| if n == 1 { format!("1 {unit}") } else { format!("{n} {unit}s") };
+    match (minutes / 60, minutes % 60) {
+        (0, 0) => String::from("less than a minute"),
+        (0, m) => plural(m, "minute"),
+        (h, 0) => plural(h, "hour"),
+        (h, m) => format!("{} {}", plural(h, "hour"), plural(m, "minute")),
+    }
+}
+
+/// Fills in an announcement such as "{name} is ready" with the alarm's
+/// {name}, {time} (e.g. "14:05"), {notes} and {in}, the time left. Only
+/// the template is filled in, braces in the alarm's name or notes are kept.
+pub fn speech_text(template: &str, alarm: &Alarm, now: UnixMoment, tz: tz::TimeZoneRef) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else { break };
+        match &rest[1..end] {
+            "name" => out.push_str(&alarm.name),
+            "time" => if let Some(dt) = alarm.end_t.as_datetime(tz) {
+                out.push_str(&format!("{:02}:{:02}", dt.hour(), dt.minute()));
+            },
+            "notes" => out.push_str(alarm.notes.as_deref().unwrap_or("")),
+            "in" => out.push_str(&spoken_duration(now.seconds_until(alarm.end_t))),
+            _ => out.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+    use crate::fixture::*;
+
+    #[test]
+    fn fills_text() {
+        let tz = eastern();
+        let now = UnixMoment::new(NOW);
+        let mut tea = Alarm::new_from_unixmoment("Tea", None, now + Duration::from_secs(300));
+        tea.notes = Some(String::from("green, 3 minutes"));
+        assert_eq!("Tea is ready", speech_text("{name} is ready", &tea, now, tz.as_ref()));
+        assert_eq!("Tea at 12:05, in 5 minutes: green, 3 minutes", speech_text("{name} at {time}, in {in}: {notes}", &tea, now, tz.as_ref()));
+        tea.name = String::from("Tea {notes}");
+        assert_eq!("Tea {notes}: green, 3 minutes {x}", speech_text("{name}: {notes} {x}", &tea, now, tz.as_ref()));
+        assert_eq!("Tea {notes} {", speech_text("{name} {", &tea, now, tz.as_ref()));
+        assert_eq!("1 hour 1 minute", spoken_duration(3680));
+        assert_eq!("less than a minute", spoken_duration(10));
+    }
+
+    #[test]
+    fn speech_commands() {
+        let espeak = SpeechTemplate::from_config("espeak-ng").unwrap();
+        assert_eq!(vec!["--", "-5 minutes"], espeak.args_for("-5 minutes"));
+        assert!(! espeak.text_on_stdin());
+        assert!(SpeechTemplate::from_config("festival").unwrap().text_on_stdin());
+        assert!(matches!(SpeechTemplate::parse("say {voice} {text}"), Err(PlayerError::UnknownPlaceholder(_))));
+    }
+}
@@ -1,16 +1,22 @@
+use crate::{dow_to_str, mon_to_str, SolarEvent};
 
 use nom::{
-    character::complete::{char, alpha1, space1, space0, digit1, u8 as parse_u8, i32 as parse_i32, i64 as parse_i64},
-    bytes::complete::tag_no_case,
-    sequence::separated_pair,
+    character::complete::{char, alpha1, one_of, space1, space0, digit1, u8 as parse_u8, i32 as parse_i32, i64 as parse_i64},
+    bytes::complete::{tag_no_case, take_while1, take_while_m_n},
+    sequence::{separated_pair, terminated, tuple, preceded},
     branch::alt,
-    combinator::{map, map_res, map_opt, verify},
-    multi::separated_list1,
+    combinator::{consumed, eof, map, map_res, map_opt, not, opt, peek, recognize, value, verify},
+    multi::{many0, separated_list1},
     IResult,
 };
 type ParseResult<'a, T> = IResult<&'a str, T>;
 
-#[derive(Debug, Eq, PartialEq)]
+mod resolve;
+pub use resolve::*;
+mod schedule;
+pub use schedule::*;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum TimePart {
     HM(u8, u8),
     MD(u8, u8),
@@ -24,62 +30,201 @@ pub enum TimePart {
     Hours(i64),
     Minutes(i64),
     Seconds(i64),
+    /// calendar days from the starting day
+    Days(i64),
+    /// calendar weeks from the starting day
+    Weeks(i64),
+    /// working days from the starting day, see WorkCalendar
+    Workdays(i64),
+    /// the same day next month
+    NextMonth,
+    /// day of the month, e.g. "the 15th"
+    MonthDay(u8),
+    /// last day of the month
+    EndOfMonth,
+    /// nth weekday of the month, e.g. "first monday". -1 is the last one.
+    NthWeekDay(i8, u8),
+    /// "+" between an anchor and durations
+    Plus,
+    /// "-" between an anchor and durations
+    Minus,
+    /// "before" between durations and an anchor
+    Before,
+    /// "after" between durations and an anchor
+    After,
+    /// sunrise, sunset or twilight at the configured location
+    Solar(SolarEvent),
+    /// IANA time zone name, e.g. "America/New_York"
+    Zone(String),
+    /// fixed offset from UTC in seconds
+    UtcOffset(i32),
 }
 
 impl TimePart {
 
-    fn parse_month_name(input: &str) -> ParseResult<TimePart> {
+    fn parse_month_name(input: &str) -> ParseResult<'_, TimePart> {
         let mp = map_opt(alpha1, parse_month);
         map(mp, TimePart::Month)(input)
     }
 
-    fn parse_weekday(input: &str) -> ParseResult<TimePart> {
+    fn parse_weekday(input: &str) -> ParseResult<'_, TimePart> {
         let wp = map_opt(alpha1, parse_weekday);
         map(wp, TimePart::WeekDay)(input)
     }
 
-    fn parse_hm(input: &str) -> ParseResult<TimePart> {
+    /// accepts "days", "day" or "d" after a number, e.g. "3 days" or "2d"
+    fn parse_days(input: &str) -> ParseResult<'_, TimePart> {
+        let unit = alt((tag_no_case("days"), tag_no_case("day"), tag_no_case("d")));
+        let p = separated_pair(parse_i64, space0, terminated(unit, not(alpha1)));
+        map(p, |(x, _)| Self::Days(x))(input)
+    }
+    /// accepts "weeks", "week" or "w" after a number, e.g. "3 weeks"
+    fn parse_weeks(input: &str) -> ParseResult<'_, TimePart> {
+        let unit = alt((tag_no_case("weeks"), tag_no_case("week"), tag_no_case("w")));
+        let p = separated_pair(parse_i64, space0, terminated(unit, not(alpha1)));
+        map(p, |(x, _)| Self::Weeks(x))(input)
+    }
+    /// accepts "3 workdays", "2 business days" or "next workday"
+    fn parse_workdays(input: &str) -> ParseResult<'_, TimePart> {
+        let unit = || alt((
+            tag_no_case("workdays"), tag_no_case("workday"),
+            tag_no_case("business days"), tag_no_case("business day"),
+        ));
+        let next = map(separated_pair(tag_no_case("next"), space1, unit()), |_| 1);
+        let counted = map(separated_pair(parse_i64, space1, terminated(unit(), not(alpha1))), |(x, _)| x);
+        map(alt((next, counted)), Self::Workdays)(input)
+    }
+    fn parse_next_month(input: &str) -> ParseResult<'_, TimePart> {
+        let p = tuple((tag_no_case("next"), space1, tag_no_case("month")));
+        map(p, |_| Self::NextMonth)(input)
+    }
+    /// accepts "15th", "1st", "22nd", optionally followed by "of the month"
+    fn parse_month_day(input: &str) -> ParseResult<'_, TimePart> {
+        let p = terminated(parse_numeric_ordinal, this_month);
+        map(verify(p, |d| (1..=31).contains(d)), Self::MonthDay)(input)
+    }
+    /// accepts "end of month", "end of the month" or "end of" followed by a month
+    fn parse_end_of_month(input: &str) -> ParseResult<'_, TimePart> {
+        let month = opt(tuple((
+            space1,
+            opt(terminated(alt((tag_no_case("the"), tag_no_case("this"))), space1)),
+            tag_no_case("month"),
+        )));
+        let p = tuple((tag_no_case("end"), space1, tag_no_case("of"), month));
+        map(p, |_| Self::EndOfMonth)(input)
+    }
+    /// accepts "first monday", "2nd tue" or "last friday of the month"
+    fn parse_nth_weekday(input: &str) -> ParseResult<'_, TimePart> {
+        let word = alt((
+            value(1, tag_no_case("first")),
+            value(2, tag_no_case("second")),
+            value(3, tag_no_case("third")),
+            value(4, tag_no_case("fourth")),
+            value(5, tag_no_case("fifth")),
+            value(-1, tag_no_case("last")),
+        ));
+        let numeric = map(verify(parse_numeric_ordinal, |n| (1..=5).contains(n)), |n| n as i8);
+        let p = separated_pair(alt((word, numeric)), space1, map_opt(alpha1, parse_weekday));
+        map(terminated(p, this_month), |(n, wd)| Self::NthWeekDay(n, wd))(input)
+    }
+
+    fn parse_hm(input: &str) -> ParseResult<'_, TimePart> {
         let p = separated_pair(parse_u8, char(':'), parse_u8);
         map(p, |(h, m)| Self::HM(h, m))(input) 
     }
 
     /// accepts formats "11 jun" or "06-11" or "6-11"
-    fn parse_md(input: &str) -> ParseResult<TimePart> {
+    fn parse_md(input: &str) -> ParseResult<'_, TimePart> {
         let p1 = separated_pair(parse_u8, space1, map_opt(alpha1, parse_month));
         let p1a = map(p1, |(d, m)| Self::MD(m, d));
         let p2 = separated_pair(parse_u8, char('-'), parse_u8);
         let p2a = map(p2, |(m, d)| Self::MD(m, d));
-        alt((p1a, p2a))(input)
+        // 29 february exists in some years
+        verify(alt((p1a, p2a)), |tp| matches!(tp, Self::MD(m, d) if valid_date((2000, *m, *d))))(input)
     }
-    fn parse_today(input: &str) -> ParseResult<TimePart> {
+    fn parse_today(input: &str) -> ParseResult<'_, TimePart> {
         map(tag_no_case("today"), |_| Self::Today)(input)
     }
-    fn parse_tomorrow(input: &str) -> ParseResult<TimePart> {
+    fn parse_tomorrow(input: &str) -> ParseResult<'_, TimePart> {
         map(tag_no_case("tomorrow"), |_| Self::Tomorrow)(input)
     }
-    fn parse_year(input: &str) -> ParseResult<TimePart> {
+    fn parse_year(input: &str) -> ParseResult<'_, TimePart> {
         let p = verify(digit1, |x: &str| x.len() == 4);
         let p1 = map_res(p, |x: &str| x.parse::<i32>());
         map(p1, Self::Year)(input)
     }
-    fn parse_ymd(input: &str) -> ParseResult<TimePart> {
-        todo!()        
+    /// accepts "2024-12-25"
+    fn parse_ymd(input: &str) -> ParseResult<'_, TimePart> {
+        let year = map_res(verify(digit1, |x: &str| x.len() == 4), |x: &str| x.parse::<i32>());
+        let p = tuple((year, char('-'), parse_u8, char('-'), parse_u8));
+        let p = verify(p, |(y, _, m, _, d)| valid_date((*y, *m, *d)));
+        map(p, |(y, _, m, _, d)| Self::YMD(y, m, d))(input)
     }
-    fn parse_hours(input: &str) -> ParseResult<TimePart> {
+    fn parse_hours(input: &str) -> ParseResult<'_, TimePart> {
         let p = separated_pair(parse_i64, space0, char('h'));
         map(p, |(x, _)| Self::Hours(x))(input) 
     }
-    fn parse_minutes(input: &str) -> ParseResult<TimePart> {
+    fn parse_minutes(input: &str) -> ParseResult<'_, TimePart> {
         let p = separated_pair(parse_i64, space0, char('m'));
         map(p, |(x, _)| Self::Minutes(x))(input) 
     }
-    fn parse_seconds(input: &str) -> ParseResult<TimePart> {
+    fn parse_seconds(input: &str) -> ParseResult<'_, TimePart> {
         let p = separated_pair(parse_i64, space0, char('s'));
         map(p, |(x, _)| Self::Seconds(x))(input) 
     }
-    pub fn parse_line(input: &str) -> ParseResult<Vec<TimePart>> {
+    /// accepts "utc", "gmt" or offsets "+02", "-0500", "+05:30"
+    fn parse_utc_offset(input: &str) -> ParseResult<'_, TimePart> {
+        let utc = map(alt((tag_no_case("utc"), tag_no_case("gmt"))), |_| Self::UtcOffset(0));
+        let two_digits = || map_res(take_while_m_n(2, 2, |c: char| c.is_ascii_digit()), |x: &str| x.parse::<i32>());
+        let offset = tuple((one_of("+-"), two_digits(), opt(preceded(opt(char(':')), two_digits()))));
+        let offset = map(offset, |(sign, h, m)| {
+            let s = h * 3600 + m.unwrap_or(0) * 60;
+            Self::UtcOffset(if sign == '-' { -s } else { s })
+        });
+        alt((offset, utc))(input)
+    }
+    /// accepts IANA names of the form "Area/Location", e.g. "America/New_York"
+    fn parse_zone(input: &str) -> ParseResult<'_, TimePart> {
+        let is_zone_char = |c: char| c.is_ascii_alphanumeric() || "_-+/".contains(c);
+        let p = recognize(tuple((alpha1, take_while1(is_zone_char))));
+        let p = verify(p, |x: &str| x.contains('/') && ! x.ends_with('/'));
+        map(p, |x: &str| Self::Zone(x.to_owned()))(input)
+    }
+    /// a lone "+" or "-", which must be followed by a space or the end,
+    /// or "before" or "after"
+    fn parse_operator(input: &str) -> ParseResult<'_, TimePart> {
+        let op = alt((value(Self::Plus, char('+')), value(Self::Minus, char('-'))));
+        let word = alt((value(Self::Before, tag_no_case("before")), value(Self::After, tag_no_case("after"))));
+        alt((terminated(op, peek(alt((space1, eof)))), terminated(word, not(alpha1))))(input)
+    }
+    /// accepts "sunrise", "sunset", "dawn", "dusk" and "civil", "nautical"
+    /// or "astronomical" followed by "dawn" or "dusk". Plain dawn and dusk
+    /// are civil twilight.
+    fn parse_solar(input: &str) -> ParseResult<'_, TimePart> {
+        let twilight = |dawn, dusk| alt((value(dawn, tag_no_case("dawn")), value(dusk, tag_no_case("dusk"))));
+        let p = alt((
+            value(SolarEvent::Sunrise, tag_no_case("sunrise")),
+            value(SolarEvent::Sunset, tag_no_case("sunset")),
+            preceded(terminated(tag_no_case("nautical"), space1), twilight(SolarEvent::NauticalDawn, SolarEvent::NauticalDusk)),
+            preceded(terminated(tag_no_case("astronomical"), space1), twilight(SolarEvent::AstronomicalDawn, SolarEvent::AstronomicalDusk)),
+            preceded(opt(terminated(tag_no_case("civil"), space1)), twilight(SolarEvent::CivilDawn, SolarEvent::CivilDusk)),
+        ));
+        map(terminated(p, not(alpha1)), Self::Solar)(input)
+    }
+    pub fn parse_line(input: &str) -> ParseResult<'_, Vec<TimePart>> {
+        let p = Self::parse_line_spans;
+        map(p, |parts| parts.into_iter().map(|(_, tp)| tp).collect())(input)
+    }
+    /// Like parse_line, also returning the text each part was parsed from
+    pub fn parse_line_spans(input: &str) -> ParseResult<'_, Vec<(&str, TimePart)>> {
         let mut p = alt((
+            Self::parse_operator,
+            Self::parse_nth_weekday,
+            Self::parse_month_day,
+            Self::parse_end_of_month,
+            alt((Self::parse_next_month, Self::parse_workdays)),
             Self::parse_hm,
+            Self::parse_ymd,
             Self::parse_md,
             Self::parse_today,
             Self::parse_tomorrow,
@@ -87,45 +232,101 @@ impl TimePart {
             Self::parse_hours,
             Self::parse_minutes,
             Self::parse_seconds,
+            Self::parse_days,
+            Self::parse_weeks,
+            alt((Self::parse_zone, Self::parse_solar)),
+            Self::parse_utc_offset,
             Self::parse_month_name,
             Self::parse_weekday,
                 ));
-        separated_list1(space1, p)(input)
+        separated_list1(space1, preceded(many0(connector), consumed(p)))(input)
     }
 
 }
 
+impl std::fmt::Display for TimePart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimePart::HM(h, m) => write!(f, "time {h:02}:{m:02}"),
+            TimePart::MD(m, d) => write!(f, "date {d} {}", mon_to_str(*m)),
+            TimePart::Today => write!(f, "today"),
+            TimePart::Tomorrow => write!(f, "tomorrow"),
+            TimePart::WeekDay(wd) => write!(f, "next {}", dow_to_str(*wd)),
+            TimePart::Month(m) => write!(f, "month {}", mon_to_str(*m)),
+            TimePart::Year(y) => write!(f, "year {y}"),
+            TimePart::YMD(y, m, d) => write!(f, "date {y}-{m:02}-{d:02}"),
+            TimePart::Hours(n) => write!(f, "{n} hours"),
+            TimePart::Minutes(n) => write!(f, "{n} minutes"),
+            TimePart::Seconds(n) => write!(f, "{n} seconds"),
+            TimePart::Days(n) => write!(f, "{n} days"),
+            TimePart::Weeks(n) => write!(f, "{n} weeks"),
+            TimePart::Workdays(n) => write!(f, "{n} working days"),
+            TimePart::NextMonth => write!(f, "next month"),
+            TimePart::MonthDay(d) => write!(f, "day {d} of the month"),
+            TimePart::EndOfMonth => write!(f, "last day of the month"),
+            TimePart::NthWeekDay(-1, wd) => write!(f, "last {} of the month", dow_to_str(*wd)),
+            TimePart::NthWeekDay(n, wd) => write!(f, "{} #{n} of the month", dow_to_str(*wd)),
+            TimePart::Plus => write!(f, "plus"),
+            TimePart::Minus => write!(f, "minus"),
+            TimePart::Before => write!(f, "before"),
+            TimePart::After => write!(f, "after"),
+            TimePart::Solar(event) => write!(f, "{event}"),
+            TimePart::Zone(name) => write!(f, "time zone {name}"),
+            TimePart::UtcOffset(0) => write!(f, "UTC"),
+            TimePart::UtcOffset(s) => {
+                let sign = if *s < 0 { '-' } else { '+' };
+                write!(f, "UTC{sign}{:02}:{:02}", s.abs() / 3600, s.abs() % 3600 / 60)
+            },
+        }
+    }
+}
+
+/// filler words that may precede a time part, e.g. "in 3 weeks on tuesday"
+fn connector(input: &str) -> ParseResult<'_, &str> {
+    let words = alt((
+        tag_no_case("in"),
+        tag_no_case("on"),
+        tag_no_case("at"),
+        tag_no_case("the"),
+        tag_no_case("of"),
+    ));
+    terminated(words, space1)(input)
+}
+
+/// optional " of the month" or " of this month"
+fn this_month(input: &str) -> ParseResult<'_, ()> {
+    let p = tuple((
+        space1, tag_no_case("of"), space1,
+        alt((tag_no_case("the"), tag_no_case("this"))), space1,
+        tag_no_case("month"),
+    ));
+    map(opt(p), |_| ())(input)
+}
+
+/// accepts "1st", "2nd", "3rd", "15th"
+fn parse_numeric_ordinal(input: &str) -> ParseResult<'_, u8> {
+    let suffix = alt((tag_no_case("st"), tag_no_case("nd"), tag_no_case("rd"), tag_no_case("th")));
+    terminated(parse_u8, terminated(suffix, not(alpha1)))(input)
+}
+
+const WEEKDAY_NAMES: [&str; 7] = ["sunday", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday"];
+const MONTH_NAMES: [&str; 12] = [
+    "january", "february", "march", "april", "may", "june",
+    "july", "august", "september", "october", "november", "december",
+];
+
+/// accepts full names and abbreviations of at least three letters
 fn parse_weekday(input: &str) -> Option<u8> {
     let input = input.to_lowercase();
-    match input.as_str() {
-        "sun" => Some(0),
-        "mon" => Some(1),
-        "tue" => Some(2),
-        "wed" => Some(3),
-        "thu" => Some(4),
-        "fri" => Some(5),
-        "sat" => Some(6),
-        _ => None,
-    }
+    if input.len() < 3 { return None; }
+    WEEKDAY_NAMES.iter().position(|n| n.starts_with(&input)).map(|i| i as u8)
 }
 
+/// accepts full names and abbreviations of at least three letters
 fn parse_month(input: &str) -> Option<u8> {
     let input = input.to_lowercase();
-    match input.as_str() {
-        "jan" => Some(1),
-        "feb" => Some(2),
-        "mar" => Some(3),
-        "apr" => Some(4),
-        "may" => Some(5),
-        "jun" => Some(6),
-        "jul" => Some(7),
-        "aug" => Some(8),
-        "sep" => Some(9),
-        "oct" => Some(10),
-        "nov" => Some(11),
-        "dec" => Some(12),
-        _ => None,
-    }
+    if input.len() < 3 { return None; }
+    MONTH_NAMES.iter().position(|n| n.starts_with(&input)).map(|i| i as u8 + 1)
 }
 
 #[cfg(test)]
@@ -178,6 +379,32 @@ mod checks {
         assert_eq!(("", TimePart::Seconds(3600)), r);
     }
     #[test]
+    fn parsing_zone() {
+        let input = "America/New_York";
+        let r = TimePart::parse_zone(input).unwrap();
+        assert_eq!(("", TimePart::Zone(String::from("America/New_York"))), r);
+        assert!(TimePart::parse_zone("sep").is_err());
+    }
+    #[test]
+    fn parsing_utc_offset() {
+        let r = TimePart::parse_utc_offset("UTC").unwrap();
+        assert_eq!(("", TimePart::UtcOffset(0)), r);
+        let r = TimePart::parse_utc_offset("-0500").unwrap();
+        assert_eq!(("", TimePart::UtcOffset(-18000)), r);
+        let r = TimePart::parse_utc_offset("+05:30").unwrap();
+        assert_eq!(("", TimePart::UtcOffset(19800)), r);
+    }
+    #[test]
+    fn parsing_ordinals() {
+        let r = TimePart::parse_nth_weekday("last friday of the month").unwrap();
+        assert_eq!(("", TimePart::NthWeekDay(-1, 5)), r);
+        let r = TimePart::parse_nth_weekday("2nd tue").unwrap();
+        assert_eq!(("", TimePart::NthWeekDay(2, 2)), r);
+        let r = TimePart::parse_month_day("15th").unwrap();
+        assert_eq!(("", TimePart::MonthDay(15)), r);
+        assert!(TimePart::parse_month_day("15h").is_err());
+    }
+    #[test]
     fn parsing_line() {
         let input = "54h 10m";
         let r = TimePart::parse_line(input).unwrap().1;
@@ -188,5 +415,41 @@ mod checks {
         let input = "tomorrow 13:45";
         let r = TimePart::parse_line(input).unwrap().1;
         assert_eq!(vec![TimePart::Tomorrow, TimePart::HM(13, 45)], r);
+        let input = "14:00 utc";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::HM(14, 0), TimePart::UtcOffset(0)], r);
+        let input = "09:00 Europe/Berlin";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::HM(9, 0), TimePart::Zone(String::from("Europe/Berlin"))], r);
+        let input = "first monday of next month";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::NthWeekDay(1, 1), TimePart::NextMonth], r);
+        let input = "end of month 17:00";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::EndOfMonth, TimePart::HM(17, 0)], r);
+        let input = "in 3 weeks on tuesday";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::Weeks(3), TimePart::WeekDay(2)], r);
+        let input = "fri 17:00 - 1d";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::WeekDay(5), TimePart::HM(17, 0), TimePart::Minus, TimePart::Days(1)], r);
+        let input = "the 15th";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::MonthDay(15)], r);
+        let input = "2024-12-25 9:00";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::YMD(2024, 12, 25), TimePart::HM(9, 0)], r);
+        let input = "in 3 business days at 9:00";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::Workdays(3), TimePart::HM(9, 0)], r);
+        let input = "next workday";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::Workdays(1)], r);
+        let input = "30m before sunset";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::Minutes(30), TimePart::Before, TimePart::Solar(SolarEvent::Sunset)], r);
+        let input = "at nautical dawn";
+        let r = TimePart::parse_line(input).unwrap().1;
+        assert_eq!(vec![TimePart::Solar(SolarEvent::NauticalDawn)], r);
     }
 }
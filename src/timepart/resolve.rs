@@ -0,0 +1,671 @@
+use crate::*;
+use super::TimePart;
+use serde::{Serialize, Deserialize};
+use tz::{DateTime, TimeZone, TimeZoneRef, datetime::FoundDateTimeKind};
+
+/// Parses and resolves a time expression such as "tomorrow 9:00" or
+/// "14:00 utc", relative to `now`.
+pub fn resolve(expr: &str, now: UnixMoment, tz: TimeZoneRef) -> Result<UnixMoment, ParseError> {
+    resolve_with(expr, now, tz, ResolveOptions::default()).map(|r| r.moment)
+}
+
+pub fn resolve_with(expr: &str, now: UnixMoment, tz: TimeZoneRef, options: ResolveOptions) -> Result<Resolved, ParseError> {
+    let tps = parse_expr(expr)?;
+    timeparts_to_unixmoment(now, tz, tps.as_slice(), options)
+}
+
+/// Parses a complete time expression. Unlike TimePart::parse_line, input
+/// that is not understood is an error.
+pub fn parse_expr(expr: &str) -> Result<Vec<TimePart>, ParseError> {
+    let tokens = parse_expr_tokens(expr)?;
+    Ok(tokens.into_iter().map(|(_, tp)| tp).collect())
+}
+
+/// Like parse_expr, also returning the text each part was read from
+pub fn parse_expr_tokens(expr: &str) -> Result<Vec<(&str, TimePart)>, ParseError> {
+    let expr = expr.trim();
+    match TimePart::parse_line_spans(expr) {
+        Ok(("", tps)) => Ok(tps),
+        Ok((rest, _)) => Err(ParseError::Syntax(rest.trim().to_owned())),
+        Err(_) => Err(ParseError::Syntax(expr.to_owned())),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResolveOptions<'a> {
+    pub resolution: Resolution,
+    pub dst: DstChoice,
+    /// for "workday" expressions, Mon-Fri without holidays if None
+    pub calendar: Option<&'a WorkCalendar>,
+    /// for sunrise and sunset expressions
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Resolved {
+    pub moment: UnixMoment,
+    pub wall_time: WallTime,
+}
+
+/// What to do with a resolved time that has already passed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Resolution {
+    /// move the time forward to the next day, week or year that matches
+    #[default]
+    NextOccurrence,
+    /// refuse the time
+    Strict,
+}
+
+/// The unit in which a time expression may be moved forward
+#[derive(Debug, Clone, Copy)]
+enum Rollover {
+    Day,
+    Week,
+    Month,
+    Year,
+    /// expression names a specific date, it cannot be moved
+    Fixed,
+}
+
+impl Rollover {
+    /// How many times to move forward before giving up. A 29 february can
+    /// be 8 years away, a fifth weekday a few months.
+    fn limit(self) -> u32 {
+        match self {
+            Rollover::Day => 100,
+            Rollover::Week => 15,
+            Rollover::Month => 48,
+            Rollover::Year => 8,
+            Rollover::Fixed => 0,
+        }
+    }
+
+    fn from_timeparts(tps: &[TimePart]) -> Self {
+        let has = |f: fn(&TimePart) -> bool| tps.iter().any(f);
+        let fixed = |tp: &TimePart| matches!(tp,
+            TimePart::Year(_) | TimePart::YMD(..) | TimePart::Today | TimePart::Tomorrow
+            | TimePart::Days(_) | TimePart::Weeks(_) | TimePart::Workdays(_) | TimePart::NextMonth
+        );
+        if has(fixed) {
+            Self::Fixed
+        } else if has(|tp| matches!(tp, TimePart::MD(..) | TimePart::Month(_))) {
+            Self::Year
+        } else if has(|tp| matches!(tp, TimePart::MonthDay(_) | TimePart::EndOfMonth | TimePart::NthWeekDay(..))) {
+            Self::Month
+        } else if has(|tp| matches!(tp, TimePart::WeekDay(_))) {
+            Self::Week
+        } else if has(|tp| matches!(tp, TimePart::HM(..) | TimePart::Solar(_))) {
+            Self::Day
+        } else {
+            Self::Fixed
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// input that is not part of the grammar
+    Syntax(String),
+    AlreadyPassed(DateTime),
+    /// the month has no day matching the expression, e.g. a fifth monday
+    NoSuchDay(i32, u8),
+    /// misuse of "+" and "-"
+    Arithmetic(&'static str),
+    UnknownZone(String),
+    /// a solar event was used without a configured location
+    NoLocation,
+    /// the work calendar has no working day within a year
+    NoWorkday,
+    /// a duration or date too far away to count with
+    OutOfRange,
+    /// the sun does not rise or set there on any of that many matching days,
+    /// e.g. in polar night
+    NoSolarEvent(SolarEvent, u32),
+    Tz(tz::error::TzError),
+}
+impl std::error::Error for ParseError {}
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Syntax(rest) => write!(f, "Could not understand time expression at: {rest}"),
+            ParseError::AlreadyPassed(dt) => write!(f, "Time already passed: {}", humanize_datetime_full(*dt)),
+            ParseError::NoSuchDay(year, month) => write!(f, "No matching day in {} {year}", mon_to_str(*month)),
+            ParseError::Arithmetic(msg) => write!(f, "Bad time arithmetic: {msg}"),
+            ParseError::UnknownZone(name) => write!(f, "Unknown time zone: {name}"),
+            ParseError::NoLocation => write!(f, "Sunrise and sunset need a location, set --location or CLOCKD_LOCATION"),
+            ParseError::OutOfRange => write!(f, "Time is too far away"),
+            ParseError::NoWorkday => write!(f, "No working day within a year, check the working weekdays and holidays"),
+            ParseError::NoSolarEvent(event, days) => write!(f, "No {event} on any of the next {days} matching days at this location"),
+            ParseError::Tz(e) => write!(f, "Could not resolve time: {e}"),
+        }
+    }
+}
+
+impl From<tz::error::TzError> for ParseError {
+    fn from(value: tz::error::TzError) -> Self {
+        Self::Tz(value)
+    }
+}
+
+impl From<tz::error::DateTimeError> for ParseError {
+    fn from(value: tz::error::DateTimeError) -> Self {
+        Self::Tz(value.into())
+    }
+}
+
+/// year, month, day
+pub type Date = (i32, u8, u8);
+
+pub fn add_days((year, month, day): Date, days: i64) -> Result<Date, ParseError> {
+    let midnight = tz::UtcDateTime::new(year, month, day, 0, 0, 0, 0)?;
+    let t = days.checked_mul(86400)
+        .and_then(|s| s.checked_add(midnight.unix_time()))
+        .ok_or(ParseError::OutOfRange)?;
+    let d = tz::UtcDateTime::from_timespec(t, 0).map_err(|_| ParseError::OutOfRange)?;
+    Ok((d.year(), d.month(), d.month_day()))
+}
+
+/// `base` plus `count` times `unit`, e.g. seconds
+fn add_scaled(base: i64, count: i64, unit: i64) -> Result<i64, ParseError> {
+    count.checked_mul(unit).and_then(|s| s.checked_add(base)).ok_or(ParseError::OutOfRange)
+}
+
+pub fn week_day((year, month, day): Date) -> Result<u8, tz::error::TzError> {
+    Ok(tz::UtcDateTime::new(year, month, day, 0, 0, 0, 0)?.week_day())
+}
+
+pub fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// The month and day exist in that year
+pub fn valid_date((year, month, day): Date) -> bool {
+    (1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day)
+}
+
+pub fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// moves the date by a number of months, clamping the day to the end of the month
+pub fn add_months((year, month, day): Date, months: i64) -> Date {
+    let m = (year as i64) * 12 + (month as i64 - 1) + months;
+    let (year, month) = (m.div_euclid(12) as i32, m.rem_euclid(12) as u8 + 1);
+    (year, month, day.min(days_in_month(year, month)))
+}
+
+/// Day of the month an expression refers to, worked out per month so that
+/// it can be rolled forward.
+#[derive(Debug, Clone, Copy)]
+enum DayRule {
+    Day(u8),
+    Last,
+    NthWeekDay(i8, u8),
+}
+
+impl DayRule {
+    /// Returns None if the month has no such day
+    fn day_in(&self, year: i32, month: u8) -> Result<Option<u8>, tz::error::TzError> {
+        let last = days_in_month(year, month);
+        let day = match *self {
+            DayRule::Day(d) => d,
+            DayRule::Last => last,
+            DayRule::NthWeekDay(n, wd) if n < 0 => {
+                let offset = (week_day((year, month, last))? + 7 - wd) % 7;
+                last - offset - 7 * (n.unsigned_abs() - 1)
+            },
+            DayRule::NthWeekDay(n, wd) => {
+                let first = 1 + (wd + 7 - week_day((year, month, 1))?) % 7;
+                first + 7 * (n as u8 - 1)
+            },
+        };
+        Ok(Some(day).filter(|d| (1..=last).contains(d)))
+    }
+}
+
+/// finds the next date with the given weekday, starting with the given date
+pub fn find_weekday(starting_date: Date, weekday: u8) -> Result<Date, ParseError> {
+    let mut counter = 7;
+    let mut date = starting_date;
+    while counter > 0 {
+        if week_day(date)? == weekday { break; }
+        date = add_days(date, 1)?;
+        counter -= 1;
+    }
+    if counter == 0 {
+        let e: std::io::Error = std::io::ErrorKind::InvalidInput.into();
+        return Err(tz::error::TzError::from(e).into());
+    }
+    Ok(date)
+}
+
+/// Which time to pick when a wall clock time is not unique
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, clap::ValueEnum)]
+pub enum DstChoice {
+    #[default]
+    Earliest,
+    Latest,
+}
+
+/// How a wall clock time maps onto the time zone's rules for that date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallTime {
+    Normal,
+    /// the time falls into the gap of a forward transition
+    Skipped,
+    /// the time is repeated by a backward transition
+    Ambiguous,
+}
+
+/// Builds a DateTime from wall clock fields according to the time zone's
+/// rules for that date.
+///
+/// A skipped time resolves to the moment of the transition (earliest) or to
+/// the wall time shifted forward by the length of the gap (latest).
+pub fn wall_datetime((year, month, day): Date, (hour, minute, second): (u8, u8, u8), tzref: TimeZoneRef, choice: DstChoice) -> Result<(DateTime, WallTime), ParseError> {
+    let found = DateTime::find(year, month, day, hour, minute, second, 0, tzref)?.into_inner();
+    let kind = if found.iter().any(|f| matches!(f, FoundDateTimeKind::Skipped { .. })) {
+        WallTime::Skipped
+    } else if found.len() > 1 {
+        WallTime::Ambiguous
+    } else {
+        WallTime::Normal
+    };
+    let picked = match choice {
+        DstChoice::Earliest => found.first(),
+        DstChoice::Latest => found.last(),
+    };
+    let dt = match (picked, choice) {
+        (Some(FoundDateTimeKind::Normal(dt)), _) => *dt,
+        (Some(FoundDateTimeKind::Skipped { after_transition, .. }), DstChoice::Earliest) => *after_transition,
+        (Some(FoundDateTimeKind::Skipped { before_transition, .. }), DstChoice::Latest) => {
+            // interpret the wall time with the offset in effect before the gap
+            let ltt = *before_transition.local_time_type();
+            let shifted = DateTime::new(year, month, day, hour, minute, second, 0, ltt)?;
+            DateTime::from_timespec(shifted.unix_time(), 0, tzref)
+                .map_err(tz::error::TzError::from)?
+        },
+        (None, _) => {
+            let e: std::io::Error = std::io::ErrorKind::InvalidInput.into();
+            return Err(tz::error::TzError::from(e).into());
+        },
+    };
+    Ok((dt, kind))
+}
+
+/// Explicit offsets that follow "+" or "-"
+#[derive(Debug, Default, Clone, Copy)]
+struct Offsets {
+    days: i64,
+    seconds: i64,
+}
+
+impl Offsets {
+    /// Adds a duration with the sign, returns false for other time parts
+    fn add(&mut self, tp: &TimePart, sign: i64) -> Result<bool, ParseError> {
+        match tp {
+            TimePart::Hours(h) => self.seconds = add_scaled(self.seconds, *h, sign * 3600)?,
+            TimePart::Minutes(m) => self.seconds = add_scaled(self.seconds, *m, sign * 60)?,
+            TimePart::Seconds(x) => self.seconds = add_scaled(self.seconds, *x, sign)?,
+            TimePart::Days(d) => self.days = add_scaled(self.days, *d, sign)?,
+            TimePart::Weeks(w) => self.days = add_scaled(self.days, *w, sign * 7)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Splits the time parts into the anchor and the offsets that follow the
+/// first operator. Bare durations may not be mixed with operators, since
+/// "15:00 10m - 5m" has no obvious reading.
+fn split_arithmetic(tps: &[TimePart]) -> Result<(&[TimePart], Offsets), ParseError> {
+    // "30m before sunset" is "sunset - 30m"
+    let relative = tps.iter().position(|tp| matches!(tp, TimePart::Before | TimePart::After));
+    if let Some(pos) = relative {
+        let sign = if matches!(tps[pos], TimePart::Before) { -1 } else { 1 };
+        let (durations, anchor) = (&tps[..pos], &tps[pos + 1..]);
+        if durations.is_empty() || anchor.is_empty() {
+            return Err(ParseError::Arithmetic("expected a duration before and a time after \"before\" or \"after\""));
+        }
+        let (anchor, mut offsets) = split_arithmetic(anchor)?;
+        for tp in durations {
+            if ! offsets.add(tp, sign)? {
+                return Err(ParseError::Arithmetic("only durations may come before \"before\" or \"after\""));
+            }
+        }
+        return Ok((anchor, offsets));
+    }
+    let first_op = tps.iter().position(|tp| matches!(tp, TimePart::Plus | TimePart::Minus));
+    let Some(first_op) = first_op else {
+        return Ok((tps, Offsets::default()));
+    };
+    let (anchor, rest) = tps.split_at(first_op);
+    if anchor.iter().any(|tp| matches!(tp, TimePart::Hours(_) | TimePart::Minutes(_) | TimePart::Seconds(_))) {
+        return Err(ParseError::Arithmetic("durations before + or - are ambiguous, write them with +"));
+    }
+    let mut offsets = Offsets::default();
+    let mut sign = 1;
+    let mut expect_duration = false;
+    for tp in rest {
+        match tp {
+            TimePart::Plus | TimePart::Minus => {
+                if expect_duration {
+                    return Err(ParseError::Arithmetic("expected a duration after + or -"));
+                }
+                sign = if matches!(tp, TimePart::Plus) { 1 } else { -1 };
+                expect_duration = true;
+                continue;
+            },
+            // a sign applies to all durations up to the next operator
+            tp if offsets.add(tp, sign)? => {},
+            TimePart::Before | TimePart::After => return Err(ParseError::Arithmetic("\"before\" and \"after\" must come first")),
+            _ => return Err(ParseError::Arithmetic("only durations may follow + or -")),
+        }
+        expect_duration = false;
+    }
+    if expect_duration {
+        return Err(ParseError::Arithmetic("expected a duration after + or -"));
+    }
+    Ok((anchor, offsets))
+}
+
+/// Returns the time zone named in the time parts, if any
+fn find_timezone(tps: &[TimePart]) -> Result<Option<TimeZone>, ParseError> {
+    let mut timezone = None;
+    for tp in tps {
+        match tp {
+            TimePart::Zone(name) => {
+                let tz = TimeZone::from_posix_tz(name)
+                    .map_err(|_| ParseError::UnknownZone(name.clone()))?;
+                timezone = Some(tz);
+            },
+            TimePart::UtcOffset(s) => {
+                let tz = TimeZone::fixed(*s)
+                    .map_err(|_| ParseError::UnknownZone(format!("UTC{s:+}s")))?;
+                timezone = Some(tz);
+            },
+            _ => continue,
+        }
+    }
+    Ok(timezone)
+}
+
+/// Resolves parsed time parts to a moment, relative to `now`.
+/// Wall clock times are read in `local_tz` unless the parts name a zone.
+pub fn timeparts_to_unixmoment(now: UnixMoment, local_tz: TimeZoneRef, tps: &[TimePart], options: ResolveOptions) -> Result<Resolved, ParseError> {
+    let ResolveOptions { resolution, dst, calendar, location } = options;
+    let default_calendar = WorkCalendar::default();
+    let calendar = calendar.unwrap_or(&default_calendar);
+    let (tps, offsets) = split_arithmetic(tps)?;
+    let zone = find_timezone(tps)?;
+    let tzref = zone.as_ref().map(TimeZone::as_ref).unwrap_or(local_tz);
+    let now_dt = DateTime::from_timespec(now.seconds(), 0, tzref)
+        .map_err(tz::error::TzError::from)?;
+    // find starting day
+    let mut date = (now_dt.year(), now_dt.month(), now_dt.month_day());
+    let mut in_weeks = false;
+    for tp in tps {
+        match tp {
+            TimePart::Tomorrow => date = add_days(date, 1)?,
+            TimePart::Days(n) => date = add_days(date, *n)?,
+            TimePart::Weeks(n) => {
+                date = add_days(date, n.checked_mul(7).ok_or(ParseError::OutOfRange)?)?;
+                in_weeks = true;
+            },
+            TimePart::Workdays(n) => date = calendar.add_workdays(date, *n)?,
+            TimePart::NextMonth => date = add_months(date, 1),
+            _ => continue,
+        }
+    }
+    for tp in tps {
+        if let TimePart::WeekDay(n) = tp {
+            date = if in_weeks {
+                // "in 3 weeks on tuesday" means the tuesday of that week
+                let monday = add_days(date, -(((week_day(date)? + 6) % 7) as i64))?;
+                add_days(monday, ((n + 6) % 7) as i64)?
+            } else {
+                find_weekday(date, *n)?
+            };
+        }
+    }
+    let (mut year, mut month, mut day) = date;
+    let mut hour = now_dt.hour();
+    let mut minute = now_dt.minute();
+    let mut second = now_dt.second();
+    let mut interval_seconds = offsets.seconds;
+    let mut day_rule = None;
+
+    for tp in tps {
+        match tp {
+            TimePart::HM(h, m) => {
+                hour = *h;
+                minute = *m;
+                second = 0;
+            },
+            TimePart::MD(m, d) => {
+                month = *m;
+                day = *d;
+            },
+            TimePart::Month(m) => month = *m,
+            TimePart::Year(y) => year = *y,
+            TimePart::YMD(y, m, d) => {
+                year = *y;
+                month = *m;
+                day = *d;
+            },
+            TimePart::MonthDay(d) => day_rule = Some(DayRule::Day(*d)),
+            TimePart::EndOfMonth => day_rule = Some(DayRule::Last),
+            TimePart::NthWeekDay(n, wd) => day_rule = Some(DayRule::NthWeekDay(*n, *wd)),
+            TimePart::Hours(h) => interval_seconds = add_scaled(interval_seconds, *h, 3600)?,
+            TimePart::Minutes(m) => interval_seconds = add_scaled(interval_seconds, *m, 60)?,
+            TimePart::Seconds(s) => interval_seconds = add_scaled(interval_seconds, *s, 1)?,
+            _ => continue,
+        }
+    }
+    let time = (hour, minute, second);
+    let solar = tps.iter().find_map(|tp| match tp {
+        TimePart::Solar(event) => Some(*event),
+        _ => None,
+    });
+    let mut day_rule = day_rule.unwrap_or(DayRule::Day(day));
+    let rollover = Rollover::from_timeparts(tps);
+    let first_month = (year, month);
+    let mut days_without_sun = 0;
+    for _ in 0..=rollover.limit() {
+        let found = match (day_rule.day_in(year, month)?, solar) {
+            (Some(d), Some(event)) => {
+                let location = location.ok_or(ParseError::NoLocation)?;
+                match event.time_on(add_days((year, month, d), offsets.days)?, location) {
+                    Some(t) => Some((DateTime::from_timespec(t, 0, tzref).map_err(tz::error::TzError::from)?, WallTime::Normal)),
+                    None => {
+                        days_without_sun += 1;
+                        None
+                    },
+                }
+            },
+            (Some(d), None) => Some(wall_datetime(add_days((year, month, d), offsets.days)?, time, tzref, dst)?),
+            (None, _) => None,
+        };
+        if let Some((dt, wall_time)) = found {
+            let t = add_scaled(dt.unix_time(), interval_seconds, 1)?;
+            if t > now.seconds() {
+                let moment = UnixMoment::new(t);
+                return Ok(Resolved { moment, wall_time });
+            }
+        }
+        match (resolution, rollover, found) {
+            (Resolution::Strict, _, Some((dt, _))) | (_, Rollover::Fixed, Some((dt, _))) => {
+                return Err(ParseError::AlreadyPassed(dt));
+            },
+            (Resolution::Strict, _, None) | (_, Rollover::Fixed, None) => {
+                return Err(ParseError::NoSuchDay(year, month));
+            },
+            (_, Rollover::Day | Rollover::Week, _) => {
+                let n = if matches!(rollover, Rollover::Day) { 1 } else { 7 };
+                let d = day_rule.day_in(year, month)?.unwrap_or(1);
+                (year, month, day) = add_days((year, month, d), n)?;
+                day_rule = DayRule::Day(day);
+            },
+            (_, Rollover::Month, _) => (year, month, _) = add_months((year, month, 1), 1),
+            (_, Rollover::Year, _) => year += 1,
+        }
+    }
+    match solar {
+        Some(event) if days_without_sun > 0 => Err(ParseError::NoSolarEvent(event, days_without_sun)),
+        _ => Err(ParseError::NoSuchDay(first_month.0, first_month.1)),
+    }
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+    use crate::fixture::*;
+
+    fn at(expr: &str) -> i64 {
+        resolve(expr, UnixMoment::new(NOW), eastern().as_ref()).unwrap().seconds()
+    }
+
+    #[test]
+    fn rolls_to_next_occurrence() {
+        // 09:00 already passed, so tomorrow
+        assert_eq!(NOW + 21 * 3600, at("09:00"));
+        assert_eq!(NOW + 3 * 3600, at("15:00"));
+        // wednesday 09:00 passed, so next week, which is in EDT
+        assert_eq!(NOW + 7 * 86400 - 4 * 3600, at("wed 09:00"));
+    }
+
+    #[test]
+    fn strict_refuses_past_time() {
+        let options = ResolveOptions { resolution: Resolution::Strict, ..Default::default() };
+        let r = resolve_with("09:00", UnixMoment::new(NOW), eastern().as_ref(), options);
+        assert!(matches!(r, Err(ParseError::AlreadyPassed(_))));
+    }
+
+    #[test]
+    fn uses_offset_of_target_date() {
+        // sunday after the spring forward transition is EDT
+        assert_eq!(NOW + 3 * 86400 + 20 * 3600, at("sun 09:00"));
+    }
+
+    #[test]
+    fn detects_skipped_time() {
+        let r = resolve_with("sun 02:30", UnixMoment::new(NOW), eastern().as_ref(), ResolveOptions::default()).unwrap();
+        assert_eq!(WallTime::Skipped, r.wall_time);
+    }
+
+    #[test]
+    fn zone_qualified() {
+        assert_eq!(NOW + 2 * 3600, at("19:00 utc"));
+    }
+
+    #[test]
+    fn calendar_relative() {
+        // first monday of april 2024 is the 1st
+        assert_eq!(1711976400, at("first monday of next month 09:00"));
+        // last friday of march 2024 is the 29th
+        assert_eq!(1711729800, at("last fri of the month 12:30"));
+        assert_eq!(1711918800, at("end of month 17:00"));
+        // the 5th already passed, so april
+        assert_eq!(1712322000, at("the 5th 9:00"));
+        // tuesday of the week three weeks from now
+        assert_eq!(1711465200, at("in 3 weeks on tuesday 11:00"));
+    }
+
+    #[test]
+    fn workdays() {
+        // thu, fri, then monday the 11th, which is in EDT
+        assert_eq!(NOW + 5 * 86400 - 4 * 3600, at("in 3 workdays at 9:00"));
+        let mut cal = WorkCalendar::default();
+        cal.read_text("2024-03-07 closed").unwrap();
+        let options = ResolveOptions { calendar: Some(&cal), ..Default::default() };
+        let r = resolve_with("next workday 9:00", UnixMoment::new(NOW), eastern().as_ref(), options).unwrap();
+        assert_eq!(NOW + 2 * 86400 - 3 * 3600, r.moment.seconds());
+        assert_eq!(NOW + 2 * 86400 - 3 * 3600, at("2024-03-08 9:00"));
+    }
+
+    #[test]
+    fn solar() {
+        let new_york = Location { latitude: 40.7128, longitude: -74.0060 };
+        let options = ResolveOptions { location: Some(new_york), ..Default::default() };
+        let sunset = |expr| resolve_with(expr, UnixMoment::new(NOW), eastern().as_ref(), options).unwrap().moment.seconds();
+        let today = SolarEvent::Sunset.time_on((2024, 3, 6), new_york).unwrap();
+        assert_eq!(today - 30 * 60, sunset("30m before sunset"));
+        assert_eq!(today - 30 * 60, sunset("sunset - 30m"));
+        // sunrise already passed, so tomorrow's
+        let tomorrow = SolarEvent::Sunrise.time_on((2024, 3, 7), new_york).unwrap();
+        assert_eq!(tomorrow, sunset("at sunrise"));
+        let r = resolve("sunset", UnixMoment::new(NOW), eastern().as_ref());
+        assert!(matches!(r, Err(ParseError::NoLocation)));
+    }
+
+    #[test]
+    fn polar_night() {
+        let pole = Location { latitude: 90.0, longitude: 0.0 };
+        let options = ResolveOptions { location: Some(pole), ..Default::default() };
+        // the sun rises at the north pole in march and does not set until september
+        let r = resolve_with("sunset", UnixMoment::new(NOW), eastern().as_ref(), options);
+        assert!(matches!(r, Err(ParseError::NoSolarEvent(SolarEvent::Sunset, 101))), "{r:?}");
+        let r = resolve_with("mon sunset", UnixMoment::new(NOW), eastern().as_ref(), options);
+        assert!(matches!(r, Err(ParseError::NoSolarEvent(SolarEvent::Sunset, 16))), "{r:?}");
+    }
+
+    #[test]
+    fn month_without_matching_day() {
+        let r = resolve("5th monday of the month", UnixMoment::new(NOW), eastern().as_ref());
+        // march 2024 has no fifth monday, april does
+        assert_eq!(1714406400, r.unwrap().seconds());
+    }
+
+    #[test]
+    fn impossible_dates() {
+        for expr in ["04-31 9:00", "02-30", "0-0", "2023-02-29"] {
+            let r = resolve(expr, UnixMoment::new(NOW), eastern().as_ref());
+            assert!(matches!(r, Err(ParseError::Syntax(_))), "{expr}");
+        }
+        // the next 29 february is in 2028
+        assert_eq!(1835445600, at("02-29 9:00"));
+        // a day the month never has gives up instead of searching forever
+        let tps = [TimePart::Month(2), TimePart::MonthDay(30)];
+        let r = timeparts_to_unixmoment(UnixMoment::new(NOW), eastern().as_ref(), &tps, ResolveOptions::default());
+        assert!(matches!(r, Err(ParseError::NoSuchDay(2024, 2))));
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(NOW + 3 * 3600 - 5 * 60, at("15:00 - 5m"));
+        assert_eq!(NOW + 3 * 3600 - 90 * 60, at("15:00 - 1h 30m"));
+        // friday 17:00 minus a day is thursday 17:00
+        assert_eq!(NOW + 86400 + 5 * 3600, at("fri 17:00 - 1d"));
+        assert_eq!(NOW + 86400 - 3 * 3600 + 2 * 3600, at("tomorrow 9:00 + 2h"));
+        // 12:05 - 10m already passed today
+        assert_eq!(NOW + 86400 - 5 * 60, at("12:05 - 10m"));
+    }
+
+    #[test]
+    fn rejects_ambiguous_arithmetic() {
+        for expr in ["15:00 10m - 5m", "15:00 -", "15:00 - fri", "15:00 + - 5m"] {
+            let r = resolve(expr, UnixMoment::new(NOW), eastern().as_ref());
+            assert!(matches!(r, Err(ParseError::Arithmetic(_))), "{expr}");
+        }
+    }
+
+    #[test]
+    fn huge_offsets() {
+        for expr in ["in 2000000000000000000 weeks", "in 9000000000000000000 days", "in 9000000000000000000h", "9:00 + 90000000000000000 d", "tomorrow + 4000000000000000000 weeks", "9223372036854775807 weeks before sunset", "9:00 + 9000000000000000000s 9000000000000000000s"] {
+            let r = resolve(expr, UnixMoment::new(NOW), eastern().as_ref());
+            assert!(matches!(r, Err(ParseError::OutOfRange)), "{expr}: {r:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_input() {
+        let r = resolve("09:00 blah", UnixMoment::new(NOW), eastern().as_ref());
+        assert!(matches!(r, Err(ParseError::Syntax(_))));
+    }
+}
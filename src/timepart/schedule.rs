@@ -0,0 +1,349 @@
+use crate::*;
+use super::{parse_month, parse_weekday, ParseResult};
+use serde::{Serialize, Deserialize};
+use tz::{DateTime, TimeZoneRef};
+use nom::{
+    character::complete::{char, alpha1, space1, u8 as parse_u8, i32 as parse_i32},
+    bytes::complete::tag,
+    sequence::{pair, preceded, separated_pair, terminated, tuple},
+    branch::alt,
+    combinator::{eof, map, map_opt, opt, peek, verify},
+    multi::separated_list1,
+};
+
+/// How many days ahead to look for the next matching date.
+/// Long enough for a schedule that only matches on Feb 29.
+const SEARCH_DAYS: usize = 3000;
+
+type Names = Option<fn(&str) -> Option<u8>>;
+
+/// A recurring calendar schedule, parsed from a 5-field cron expression
+/// ("30 9 * * 1-5") or a systemd OnCalendar spec ("Mon..Fri *-*-* 09:30").
+/// Each field is a bitmask of the values it matches.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Schedule {
+    source: String,
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    month_days: u64,
+    months: u64,
+    week_days: u64,
+    /// inclusive ranges, empty matches any year
+    years: Vec<(i32, i32)>,
+    /// cron matches either day field when both are restricted
+    day_or: bool,
+}
+
+fn bits(lo: u8, hi: u8) -> u64 {
+    (lo..=hi).fold(0, |m, v| m | 1 << v)
+}
+
+fn members(mask: u64) -> impl Iterator<Item = u8> {
+    (0..64).filter(move |b| mask & 1 << b != 0)
+}
+
+/// value, range or step, e.g. "5", "1-5", "*/15", "mon..fri", "0/10"
+fn field<'a>(lo: u8, hi: u8, names: Names, range_sep: &'static str) -> impl FnMut(&'a str) -> ParseResult<'a, u64> {
+    move |input: &'a str| {
+        let value = |input: &'a str| -> ParseResult<'a, u8> {
+            match names {
+                Some(f) => alt((parse_u8, map_opt(alpha1, f)))(input),
+                None => parse_u8(input),
+            }
+        };
+        let bounds = alt((
+            map(char('*'), |_| None),
+            map(pair(value, opt(preceded(tag(range_sep), value))), Some),
+        ));
+        let item = map_opt(pair(bounds, opt(preceded(char('/'), parse_u8))), |(b, step)| {
+            let (a, z) = match b {
+                None => (lo, hi),
+                Some((a, Some(z))) => (a, z),
+                // "0/10" means from 0 to the end of the range
+                Some((a, None)) if step.is_some() => (a, hi),
+                Some((a, None)) => (a, a),
+            };
+            let step = step.unwrap_or(1);
+            if step == 0 || a < lo || z > hi || a > z { return None; }
+            Some((a..=z).step_by(step as usize).fold(0u64, |m, v| m | 1 << v))
+        });
+        map(separated_list1(char(','), item), |items| items.into_iter().fold(0, |m, x| m | x))(input)
+    }
+}
+
+/// "*", "2024" or "2024..2026", comma separated
+fn year_field(input: &str) -> ParseResult<'_, Vec<(i32, i32)>> {
+    let range = map(pair(parse_i32, opt(preceded(tag(".."), parse_i32))), |(a, z)| (a, z.unwrap_or(a)));
+    alt((
+        map(char('*'), |_| Vec::new()),
+        separated_list1(char(','), range),
+    ))(input)
+}
+
+impl Schedule {
+    /// Parses a cron expression, falling back to an OnCalendar spec
+    pub fn parse(input: &str) -> Result<Self, ParseError> {
+        let input = input.trim();
+        if let Ok((_, s)) = terminated(Self::parse_cron, eof)(input) {
+            return Ok(s);
+        }
+        match terminated(Self::parse_oncalendar, eof)(input) {
+            Ok((_, s)) => Ok(s),
+            Err(_) => Err(ParseError::Syntax(input.to_owned())),
+        }
+    }
+
+    /// Parses a list of weekdays such as "mon-fri" or "sun..thu,sat" into a
+    /// bitmask, bit 0 being sunday
+    pub fn parse_weekdays(input: &str) -> Option<u8> {
+        let list = |sep| terminated(field(0, 7, Some(parse_weekday), sep), eof);
+        let (_, mask) = alt((list(".."), list("-")))(input.trim()).ok()?;
+        let mask = if mask & 1 << 7 != 0 { (mask & !(1 << 7)) | 1 } else { mask };
+        Some(mask as u8)
+    }
+
+    fn from_fields(source: &str, fields: [u64; 6], years: Vec<(i32, i32)>, day_or: bool) -> Self {
+        let [seconds, minutes, hours, month_days, months, week_days] = fields;
+        Self { source: source.to_owned(), seconds, minutes, hours, month_days, months, week_days, years, day_or }
+    }
+
+    /// "minute hour day-of-month month day-of-week", or a macro like "@daily"
+    fn parse_cron(input: &str) -> ParseResult<'_, Schedule> {
+        let expanded = match input.to_lowercase().as_str() {
+            "@hourly" => Some("0 * * * *"),
+            "@daily" | "@midnight" => Some("0 0 * * *"),
+            "@weekly" => Some("0 0 * * 0"),
+            "@monthly" => Some("0 0 1 * *"),
+            "@yearly" | "@annually" => Some("0 0 1 1 *"),
+            _ => None,
+        };
+        if let Some(expanded) = expanded {
+            let (_, mut s) = Self::parse_cron(expanded).expect("cron macro should parse");
+            s.source = input.to_owned();
+            return Ok(("", s));
+        }
+        let p = tuple((
+            terminated(field(0, 59, None, "-"), space1),
+            terminated(field(0, 23, None, "-"), space1),
+            terminated(field(1, 31, None, "-"), space1),
+            terminated(field(1, 12, Some(parse_month), "-"), space1),
+            field(0, 7, Some(parse_weekday), "-"),
+        ));
+        map(p, |(minutes, hours, month_days, months, mut week_days)| {
+            // 7 is another name for sunday
+            if week_days & 1 << 7 != 0 { week_days = (week_days & !(1 << 7)) | 1; }
+            let day_or = month_days != bits(1, 31) && week_days != bits(0, 6);
+            Self::from_fields(input, [bits(0, 0), minutes, hours, month_days, months, week_days], Vec::new(), day_or)
+        })(input)
+    }
+
+    /// "[weekdays] [[year-]month-day] [hour:minute[:second]]", or a
+    /// shorthand like "daily"
+    fn parse_oncalendar(input: &str) -> ParseResult<'_, Schedule> {
+        let expanded = match input.to_lowercase().as_str() {
+            "minutely" => Some("*-*-* *:*:00"),
+            "hourly" => Some("*-*-* *:00:00"),
+            "daily" => Some("*-*-* 00:00:00"),
+            "weekly" => Some("Mon *-*-* 00:00:00"),
+            "monthly" => Some("*-*-01 00:00:00"),
+            "quarterly" => Some("*-01,04,07,10-01 00:00:00"),
+            "yearly" | "annually" => Some("*-01-01 00:00:00"),
+            _ => None,
+        };
+        if let Some(expanded) = expanded {
+            let (_, mut s) = Self::parse_oncalendar(expanded).expect("calendar shorthand should parse");
+            s.source = input.to_owned();
+            return Ok(("", s));
+        }
+        let week_days = preceded(peek(alpha1), field(0, 6, Some(parse_weekday), ".."));
+        let month_day = separated_pair(field(1, 12, None, ".."), char('-'), field(1, 31, None, ".."));
+        let full_date = map(
+            tuple((year_field, char('-'), field(1, 12, None, ".."), char('-'), field(1, 31, None, ".."))),
+            |(y, _, m, _, d)| (y, (m, d)),
+        );
+        let date = alt((full_date, map(month_day, |md| (Vec::new(), md))));
+        let time = tuple((
+            field(0, 23, None, ".."),
+            preceded(char(':'), field(0, 59, None, "..")),
+            opt(preceded(char(':'), field(0, 59, None, ".."))),
+        ));
+        let p = tuple((
+            opt(terminated(week_days, alt((space1, eof)))),
+            opt(terminated(date, alt((space1, eof)))),
+            opt(time),
+        ));
+        // at least one of the parts must be present
+        let p = verify(p, |(wd, date, time)| wd.is_some() || date.is_some() || time.is_some());
+        map(p, |(wd, date, time)| {
+            let week_days = wd.unwrap_or(bits(0, 6));
+            let (years, (months, month_days)) = date.unwrap_or((Vec::new(), (bits(1, 12), bits(1, 31))));
+            let (hours, minutes, seconds) = time.unwrap_or((bits(0, 0), bits(0, 0), None));
+            let seconds = seconds.unwrap_or(bits(0, 0));
+            Self::from_fields(input, [seconds, minutes, hours, month_days, months, week_days], years, false)
+        })(input)
+    }
+
+    fn matches_date(&self, (year, month, day): Date) -> Result<bool, tz::error::TzError> {
+        let year_ok = self.years.is_empty() || self.years.iter().any(|(a, z)| (*a..=*z).contains(&year));
+        let month_ok = self.months & 1 << month != 0;
+        let dom = self.month_days & 1 << day != 0;
+        let dow = self.week_days & 1 << week_day((year, month, day))? != 0;
+        let day_ok = if self.day_or { dom || dow } else { dom && dow };
+        Ok(year_ok && month_ok && day_ok)
+    }
+
+    /// Returns the first time after `after` that matches, as wall clock time in `tz`
+    pub fn next_after(&self, after: UnixMoment, tz: TimeZoneRef) -> Option<UnixMoment> {
+        let start = DateTime::from_timespec(after.seconds(), 0, tz).ok()?;
+        let start_date = (start.year(), start.month(), start.month_day());
+        let start_time = (start.hour(), start.minute(), start.second());
+        let mut date = start_date;
+        for _ in 0..SEARCH_DAYS {
+            if self.matches_date(date).ok()? {
+                for h in members(self.hours) {
+                    for m in members(self.minutes) {
+                        for s in members(self.seconds) {
+                            if date == start_date && (h, m, s) <= start_time { continue; }
+                            let (dt, _) = wall_datetime(date, (h, m, s), tz, DstChoice::Earliest).ok()?;
+                            if dt.unix_time() > after.seconds() {
+                                return Some(UnixMoment::new(dt.unix_time()));
+                            }
+                        }
+                    }
+                }
+            }
+            date = add_days(date, 1).ok()?;
+        }
+        None
+    }
+
+    /// Like next_after, but skips fire times that fall on days off in `calendar`
+    pub fn next_working_after(&self, after: UnixMoment, tz: TimeZoneRef, calendar: &WorkCalendar) -> Option<UnixMoment> {
+        let mut t = after;
+        for _ in 0..SEARCH_DAYS {
+            let next = self.next_after(t, tz)?;
+            let dt = DateTime::from_timespec(next.seconds(), 0, tz).ok()?;
+            let date = (dt.year(), dt.month(), dt.month_day());
+            if calendar.is_workday(date) { return Some(next); }
+            // continue from the end of that day
+            let (end, _) = wall_datetime(date, (23, 59, 59), tz, DstChoice::Latest).ok()?;
+            t = UnixMoment::new(end.unix_time().max(next.seconds()));
+        }
+        None
+    }
+
+    /// Writes the schedule as an iCalendar RRULE. Year ranges and cron's
+    /// either-day-field matching have no RRULE equivalent and are left out.
+    pub fn to_rrule(&self) -> String {
+        let list = |mask: u64| members(mask).map(|v| v.to_string()).collect::<Vec<_>>().join(",");
+        let mut rule = String::from("FREQ=DAILY");
+        if self.months != bits(1, 12) {
+            rule += &format!(";BYMONTH={}", list(self.months));
+        }
+        if self.month_days != bits(1, 31) {
+            rule += &format!(";BYMONTHDAY={}", list(self.month_days));
+        }
+        if self.week_days != bits(0, 6) {
+            const CODES: [&str; 7] = ["SU", "MO", "TU", "WE", "TH", "FR", "SA"];
+            let days: Vec<&str> = members(self.week_days).map(|d| CODES[d as usize]).collect();
+            rule += &format!(";BYDAY={}", days.join(","));
+        }
+        rule += &format!(";BYHOUR={};BYMINUTE={};BYSECOND={}", list(self.hours), list(self.minutes), list(self.seconds));
+        rule
+    }
+
+    /// Returns up to `n` upcoming times after `after`
+    pub fn next_n(&self, after: UnixMoment, tz: TimeZoneRef, n: usize) -> Vec<UnixMoment> {
+        let mut fires = Vec::with_capacity(n);
+        let mut t = after;
+        while fires.len() < n {
+            match self.next_after(t, tz) {
+                Some(next) => {
+                    fires.push(next);
+                    t = next;
+                },
+                None => break,
+            }
+        }
+        fires
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<Schedule> for String {
+    fn from(value: Schedule) -> Self {
+        value.source
+    }
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+    use crate::fixture::*;
+
+    fn fires(expr: &str, n: usize) -> Vec<i64> {
+        let s = Schedule::parse(expr).unwrap();
+        s.next_n(UnixMoment::new(NOW), eastern().as_ref(), n).iter().map(|t| t.seconds()).collect()
+    }
+
+    #[test]
+    fn cron_weekdays() {
+        // thu 7th, fri 8th, then mon 11th which is in EDT
+        let thu = NOW + 86400 - 150 * 60;
+        assert_eq!(vec![thu, thu + 86400, thu + 4 * 86400 - 3600], fires("30 9 * * 1-5", 3));
+    }
+
+    #[test]
+    fn oncalendar_weekdays() {
+        assert_eq!(fires("30 9 * * 1-5", 3), fires("Mon..Fri *-*-* 09:30:00", 3));
+        assert_eq!(fires("*/15 * * * *", 4), fires("*:0/15", 4));
+        assert_eq!(fires("@monthly", 2), fires("monthly", 2));
+    }
+
+    #[test]
+    fn cron_day_fields_are_ored() {
+        // the 8th is a friday, the 9th a saturday
+        let fri = NOW + 2 * 86400 - 12 * 3600;
+        assert_eq!(vec![fri, fri + 86400], fires("0 0 9 * 5", 2));
+    }
+
+    #[test]
+    fn skips_days_off() {
+        let mut cal = WorkCalendar::default();
+        cal.read_text("2024-03-07 closed").unwrap();
+        let s = Schedule::parse("daily").unwrap();
+        let t = s.next_working_after(UnixMoment::new(NOW), eastern().as_ref(), &cal).unwrap();
+        // the 7th is a holiday, so midnight starting the 8th
+        assert_eq!(NOW + 36 * 3600, t.seconds());
+        assert_eq!(Some(0b0111110), Schedule::parse_weekdays("mon-fri"));
+        assert_eq!(Some(0b1011111), Schedule::parse_weekdays("sun..thu,sat"));
+        assert_eq!(None, Schedule::parse_weekdays("weekdays"));
+    }
+
+    #[test]
+    fn rrule() {
+        let s = Schedule::parse("30 9 * * 1-5").unwrap();
+        assert_eq!("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR;BYHOUR=9;BYMINUTE=30;BYSECOND=0", s.to_rrule());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(Schedule::parse("61 * * * *").is_err());
+        assert!(Schedule::parse("every tuesday").is_err());
+    }
+}
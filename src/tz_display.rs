@@ -31,6 +31,31 @@ pub fn mon_to_str(mon: u8) -> &'static str {
     }
 }
 
+pub fn humanize_seconds(mut s: i64) -> String {
+    let ago = if s < 0 {
+        s *= -1;
+        " ago"
+    } else {
+        ""
+    };
+    let d = s / 86400;
+    s -= d * 86400;
+    let h = s / 3600;
+    s -= h * 3600;
+    if d > 0 {
+        return format!("{d}d {h}h{ago}");
+    }
+    let m = s / 60;
+    s -= m * 60;
+    if h > 0 {
+        return format!("{h}h {m}m{ago}");
+    }
+    if m > 0 {
+        return format!("{m}m {s}s{ago}");
+    }
+    format!("{s}s{ago}")
+}
+
 pub fn humanize_datetime(dt: DateTime) -> String {
     format!(
         "{:3} {:02} {:3} {:02}:{:02}",
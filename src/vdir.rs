@@ -0,0 +1,92 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use clockd::*;
+use inotify::{Inotify, WatchMask};
+use tracing::{debug, error, warn};
+use crate::Sender;
+
+/// how long to wait for more changes before syncing, vdirsyncer writes
+/// many files in a row
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// The collection directories of a vdir: the directory itself and its
+/// immediate subdirectories
+fn collections(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![dir.to_owned()];
+    if let Ok(entries) = fs::read_dir(dir) {
+        dirs.extend(entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()));
+    }
+    dirs
+}
+
+/// Reads every .ics file of the vdir into alarm specs
+fn read_vdir(dir: &Path) -> Vec<AlarmSpec> {
+    let tz = match tz::TimeZone::local() {
+        Ok(tz) => tz,
+        Err(e) => {
+            error!("vdir: could not get local time zone: {e}");
+            return Vec::new();
+        },
+    };
+    let now = UnixMoment::now();
+    let mut specs = Vec::new();
+    for collection in collections(dir) {
+        let Ok(entries) = fs::read_dir(&collection) else { continue };
+        for path in entries.flatten().map(|e| e.path()) {
+            if path.extension() != Some("ics".as_ref()) { continue; }
+            let import = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| import_ics(&text, now, tz.as_ref()).map_err(|e| e.to_string()));
+            match import {
+                Ok(import) => {
+                    for w in import.warnings {
+                        warn!("vdir {path:?}: {w}");
+                    }
+                    specs.extend(import.specs.into_iter().map(AlarmSpec::managed));
+                },
+                Err(e) => error!("vdir {path:?}: {e}"),
+            }
+        }
+    }
+    specs
+}
+
+/// call with spawn_blocking. Syncs the alarms with the vdir on start and
+/// whenever a file in it changes.
+pub fn start_vdir_watch(dir: PathBuf, cmd_tx: Sender<AppCommand>) {
+    let mut inotify = match Inotify::init() {
+        Ok(i) => i,
+        Err(e) => {
+            error!("vdir: could not start inotify: {e}");
+            return;
+        },
+    };
+    let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::MOVED_FROM
+        | WatchMask::DELETE | WatchMask::CREATE;
+    let mut buffer = [0; 4096];
+    loop {
+        // watch again each time, for collections that were added
+        for collection in collections(&dir) {
+            if let Err(e) = inotify.watches().add(&collection, mask) {
+                error!("vdir: could not watch {collection:?}: {e}");
+            }
+        }
+        let specs = read_vdir(&dir);
+        debug!("vdir: syncing {} alarms from {dir:?}", specs.len());
+        if cmd_tx.send_blocking(AppCommand::SyncManaged(specs)).is_err() {
+            return;
+        }
+        if let Err(e) = inotify.read_events_blocking(&mut buffer) {
+            error!("vdir: {e}");
+            return;
+        }
+        // drain the rest of the burst
+        loop {
+            std::thread::sleep(SETTLE_TIME);
+            if inotify.read_events(&mut buffer).is_err() { break; }
+        }
+    }
+}
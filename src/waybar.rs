@@ -19,10 +19,13 @@ struct WaybarUpdate<'a> {
     now: UnixMoment,
     #[serde(skip)]
     tzref: tz::TimeZoneRef<'a>,
+    /// location for the sunrise and sunset line, if enabled
+    #[serde(skip)]
+    sun: Option<Location>,
 }
 
 impl<'a> WaybarUpdate<'a> {
-    fn new(tzref: TimeZoneRef<'a>) -> Self {
+    fn new(tzref: TimeZoneRef<'a>, sun: Option<Location>) -> Self {
         Self {
             text: String::new(),
             class: String::new(),
@@ -31,6 +34,7 @@ impl<'a> WaybarUpdate<'a> {
             time_display_full: String::new(),
             now: UnixMoment::now(),
             tzref,
+            sun,
         }
     }
     fn update_time(&mut self) {
@@ -76,6 +80,20 @@ fn update_tooltip<'a, 'b>(
         writeln!(update.tooltip, "{TOOLTIP_HRULE}");
     }
     write!(update.tooltip, "{}", update.time_display_full);
+    if let Some(location) = update.sun {
+        write_sun_times(update, location);
+    }
+}
+
+fn write_sun_times(update: &mut WaybarUpdate, location: Location) {
+    let Some(dt) = update.now.as_datetime(update.tzref) else { return };
+    let date = (dt.year(), dt.month(), dt.month_day());
+    let hm = |event: SolarEvent| event.time_on(date, location)
+        .and_then(|t| UnixMoment::new(t).as_datetime(update.tzref))
+        .map(|dt| format!("{:02}:{:02}", dt.hour(), dt.minute()))
+        .unwrap_or(String::from("--:--"));
+    let (rise, set) = (hm(SolarEvent::Sunrise), hm(SolarEvent::Sunset));
+    write!(update.tooltip, "\nsunrise {rise}  sunset {set}");
 }
 
 fn update_text_clock(update: &mut WaybarUpdate, pending_len: usize) {
@@ -110,8 +128,9 @@ pub async fn waybar_display_server(
     alarm_list: Arc<RwLock<AlarmList>>,
     mut event_rx: broadcast::Receiver<AppEvent>,
     timezone: tz::TimeZone,
+    sun: Option<Location>,
     ) {
-    let mut update = WaybarUpdate::new(timezone.as_ref());
+    let mut update = WaybarUpdate::new(timezone.as_ref(), sun);
     let mut auto_switch_mode = true;
     let mut display_mode = WaybarDisplayMode::Clock;
     let mut now = UnixMoment::now();
@@ -164,6 +183,20 @@ pub async fn waybar_display_server(
                 let x = alarm_list.read().unwrap();
                 update_display(&mut update, x, display_mode);
             },
+            AppEvent::AckAlarm(_) => {
+                let x = alarm_list.read().unwrap();
+                update_display(&mut update, x, display_mode);
+            },
+            AppEvent::Snooze(_) => {
+                let x = alarm_list.read().unwrap();
+                // snoozing the last ringing alarm ends the ringing display
+                if x.alarming_len() == 0 {
+                    auto_switch_mode = true;
+                    display_mode = WaybarDisplayMode::Clock;
+                    update.class.clear();
+                }
+                update_display(&mut update, x, display_mode);
+            },
             AppEvent::SwitchDisplay => {
                 auto_switch_mode = false;
                 let x = alarm_list.read().unwrap();
@@ -182,6 +215,10 @@ pub async fn waybar_display_server(
                 update_display(&mut update, x, display_mode);
             },
             AppEvent::NewListener => {},
+            AppEvent::Warn(_) | AppEvent::Added(_) => {},
+            AppEvent::Chimes(_) | AppEvent::DoNotDisturb(_) => {},
+            AppEvent::AudioError(_) => {},
+            AppEvent::Playback(_) => {},
         }
     }
     unreachable!()
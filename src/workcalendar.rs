@@ -0,0 +1,260 @@
+use crate::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tz::{DateTime, TimeZoneRef};
+
+/// Working weekdays plus a set of holidays, used for "workday" expressions
+/// and for recurring alarms that should not go off on days off.
+#[derive(Debug, Clone)]
+pub struct WorkCalendar {
+    /// bitmask of working weekdays, bit 0 is sunday
+    working_days: u8,
+    /// holiday dates and their descriptions
+    holidays: BTreeMap<Date, String>,
+}
+
+/// days to look through for a working day before giving up, in case none
+/// are worked
+pub const WORKDAY_SEARCH: u64 = 366;
+/// the most working days to count, about a century
+const MAX_WORKDAYS: u64 = 100 * 366;
+
+impl Default for WorkCalendar {
+    /// monday to friday, no holidays
+    fn default() -> Self {
+        Self { working_days: 0b0111110, holidays: BTreeMap::new() }
+    }
+}
+
+/// Why a date is or is not a working day
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DayKind {
+    Working,
+    /// weekday that is not worked
+    DayOff,
+    Holiday(String),
+}
+
+#[derive(Debug)]
+pub enum CalendarError {
+    Io(std::io::Error),
+    /// line number and content of a line that is not a date
+    BadLine(usize, String),
+    BadWorkdays(String),
+}
+impl std::error::Error for CalendarError {}
+impl std::fmt::Display for CalendarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalendarError::Io(e) => write!(f, "Could not read holidays: {e}"),
+            CalendarError::BadLine(n, line) => write!(f, "Bad holiday on line {n}: {line}"),
+            CalendarError::BadWorkdays(s) => write!(f, "Bad working weekdays: {s}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for CalendarError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// "2024-12-25"
+fn parse_date(s: &str) -> Option<Date> {
+    let mut it = s.splitn(3, '-');
+    let y = it.next()?.parse().ok()?;
+    let m = it.next()?.parse().ok()?;
+    let d = it.next()?.parse().ok()?;
+    (1..=12).contains(&m).then_some(())?;
+    (1..=days_in_month(y, m)).contains(&d).then_some((y, m, d))
+}
+
+/// The day that "2024-12-25", "today" or "next friday" falls on. Unlike
+/// alarm times, days that have passed are allowed.
+pub fn resolve_day(expr: &str, now: UnixMoment, tz: TimeZoneRef, options: ResolveOptions) -> Result<Date, ParseError> {
+    if let Some(date) = parse_date(expr.trim()) {
+        return Ok(date);
+    }
+    let dt = match resolve_with(expr, now, tz, options) {
+        Ok(r) => DateTime::from_timespec(r.moment.seconds(), 0, tz).map_err(tz::error::TzError::from)?,
+        Err(ParseError::AlreadyPassed(dt)) => dt,
+        Err(e) => return Err(e),
+    };
+    Ok((dt.year(), dt.month(), dt.month_day()))
+}
+
+/// "20241225", as used by iCalendar DATE values
+fn parse_basic_date(s: &str) -> Option<Date> {
+    let s = s.get(..8)?;
+    parse_date(&format!("{}-{}-{}", &s[..4], &s[4..6], &s[6..]))
+}
+
+impl WorkCalendar {
+    /// `working_days` is a list or range of weekdays such as "mon-fri" or
+    /// "sun-thu,sat". Holidays are read from an iCalendar file if the path
+    /// ends in .ics, otherwise from a text file with one date or date range
+    /// per line, optionally followed by a description:
+    ///
+    /// 2024-12-25 Christmas
+    /// 2024-12-27..2024-12-31 shutdown
+    pub fn load(holidays: Option<&Path>, working_days: &str) -> Result<Self, CalendarError> {
+        let working_days = Schedule::parse_weekdays(working_days)
+            .ok_or(CalendarError::BadWorkdays(working_days.to_owned()))?;
+        let mut cal = Self { working_days, ..Self::default() };
+        let Some(path) = holidays else { return Ok(cal); };
+        let text = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("ics")) {
+            cal.read_ics(&text);
+        } else {
+            cal.read_text(&text)?;
+        }
+        Ok(cal)
+    }
+
+    fn add_range(&mut self, first: Date, last: Date, description: &str) {
+        let mut date = first;
+        while date <= last {
+            self.holidays.insert(date, description.to_owned());
+            match add_days(date, 1) {
+                Ok(d) => date = d,
+                Err(_) => break,
+            }
+        }
+    }
+
+    pub(crate) fn read_text(&mut self, text: &str) -> Result<(), CalendarError> {
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let (dates, description) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (first, last) = dates.split_once("..").unwrap_or((dates, dates));
+            let bad = || CalendarError::BadLine(n + 1, line.to_owned());
+            let first = parse_date(first).ok_or_else(bad)?;
+            let last = parse_date(last).ok_or_else(bad)?;
+            self.add_range(first, last, description.trim());
+        }
+        Ok(())
+    }
+
+    /// Reads all-day VEVENTs. DTEND is exclusive, as in RFC 5545.
+    fn read_ics(&mut self, text: &str) {
+        let (mut start, mut end, mut summary) = (None, None, String::new());
+        for p in unfold(text).iter().filter_map(|line| Property::parse(line)) {
+            let value = p.value.trim_end();
+            match p.name.as_str() {
+                "BEGIN" if value.eq_ignore_ascii_case("VEVENT") => (start, end, summary) = (None, None, String::new()),
+                "DTSTART" => start = parse_basic_date(value),
+                "DTEND" => end = parse_basic_date(value).and_then(|d| add_days(d, -1).ok()),
+                "SUMMARY" => summary = unescape(value),
+                "END" if value.eq_ignore_ascii_case("VEVENT") => {
+                    if let Some(first) = start {
+                        self.add_range(first, end.unwrap_or(first).max(first), &summary);
+                    }
+                },
+                _ => continue,
+            }
+        }
+    }
+
+    pub fn day_kind(&self, date: Date) -> DayKind {
+        if let Some(description) = self.holidays.get(&date) {
+            return DayKind::Holiday(description.clone());
+        }
+        match week_day(date) {
+            Ok(wd) if self.working_days & 1 << wd != 0 => DayKind::Working,
+            _ => DayKind::DayOff,
+        }
+    }
+
+    pub fn is_workday(&self, date: Date) -> bool {
+        self.day_kind(date) == DayKind::Working
+    }
+
+    /// Moves forward by `n` working days, or back if `n` is negative. With
+    /// n = 0, returns the date itself if it is a working day, or else the
+    /// next one.
+    pub fn add_workdays(&self, mut date: Date, n: i64) -> Result<Date, ParseError> {
+        let step = if n < 0 { -1 } else { 1 };
+        let mut remaining = n.unsigned_abs();
+        if remaining > MAX_WORKDAYS {
+            return Err(ParseError::OutOfRange);
+        }
+        if remaining == 0 {
+            for _ in 0..WORKDAY_SEARCH {
+                if self.is_workday(date) { return Ok(date); }
+                date = add_days(date, 1)?;
+            }
+            return Err(ParseError::NoWorkday);
+        }
+        // bounded, in case no weekday is a working day
+        for _ in 0..remaining * 7 + WORKDAY_SEARCH {
+            date = add_days(date, step)?;
+            if self.is_workday(date) {
+                remaining -= 1;
+                if remaining == 0 { return Ok(date); }
+            }
+        }
+        Err(ParseError::NoWorkday)
+    }
+}
+
+#[cfg(test)]
+mod checks {
+    use super::*;
+
+    #[test]
+    fn reads_holiday_text() {
+        let mut cal = WorkCalendar::default();
+        cal.read_text("# comment\n2024-12-25 Christmas\n2024-12-30..2024-12-31 shutdown\n").unwrap();
+        assert_eq!(DayKind::Holiday(String::from("Christmas")), cal.day_kind((2024, 12, 25)));
+        assert_eq!(DayKind::Holiday(String::from("shutdown")), cal.day_kind((2024, 12, 31)));
+        assert_eq!(DayKind::DayOff, cal.day_kind((2024, 12, 28)));
+        assert_eq!(DayKind::Working, cal.day_kind((2024, 12, 27)));
+        // tue 24th, then 25th holiday, 26th, 27th, weekend, shutdown
+        assert_eq!((2025, 1, 1), cal.add_workdays((2024, 12, 24), 3).unwrap());
+        assert!(cal.read_text("2024-13-01").is_err());
+        // back from thu 2nd: 1st, shutdown, weekend, 27th, 26th
+        assert_eq!((2024, 12, 26), cal.add_workdays((2025, 1, 2), -3).unwrap());
+        assert_eq!((2024, 12, 26), cal.add_workdays((2024, 12, 25), 0).unwrap());
+        let never = WorkCalendar { working_days: 0, ..WorkCalendar::default() };
+        assert!(matches!(never.add_workdays((2024, 12, 24), 0), Err(ParseError::NoWorkday)));
+        assert!(matches!(never.add_workdays((2024, 12, 24), -2), Err(ParseError::NoWorkday)));
+        assert!(matches!(cal.add_workdays((2024, 12, 24), 99999999999999), Err(ParseError::OutOfRange)));
+    }
+
+    #[test]
+    fn daily_alarm_without_workdays() {
+        let utc = tz::TimeZone::utc();
+        // Wed 2024-03-06 12:00 UTC
+        let now = UnixMoment::new(1709726400);
+        let spec = AlarmSpec::from_expr(String::from("x"), None, String::from("sun 9:00"))
+            .repeat_daily(String::from("sun 9:00"))
+            .workdays_only();
+        let r = spec.into_alarm(now, utc.as_ref(), ResolveOptions::default());
+        assert!(matches!(r, Err(AlarmSpecError::BadTime(ParseError::NoWorkday))));
+        let r = Alarm::next_daily("mon 9:00", now, utc.as_ref(), ResolveOptions::default(), true);
+        assert_eq!(1710147600, r.unwrap().seconds());
+    }
+
+    #[test]
+    fn resolves_past_days() {
+        let utc = tz::TimeZone::utc();
+        // Wed 2024-03-06 12:00 UTC
+        let now = UnixMoment::new(1709726400);
+        let day = |expr| resolve_day(expr, now, utc.as_ref(), ResolveOptions::default());
+        assert_eq!((2024, 3, 6), day("today").unwrap());
+        assert_eq!((2020, 1, 1), day("2020-01-01").unwrap());
+        assert_eq!((2024, 3, 7), day("tomorrow").unwrap());
+        assert_eq!((2024, 3, 8), day("fri").unwrap());
+        assert!(day("2024-02-30").is_err());
+    }
+
+    #[test]
+    fn reads_holiday_ics() {
+        let mut cal = WorkCalendar::default();
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nDTSTART;VALUE=DATE:20241225\r\nDTEND;VALUE=DATE:20241227\r\nSUMMARY:Christmas\\, Boxing\r\n  Day\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+        cal.read_ics(ics);
+        assert_eq!(DayKind::Holiday(String::from("Christmas, Boxing Day")), cal.day_kind((2024, 12, 26)));
+        assert!(cal.is_workday((2024, 12, 27)));
+    }
+}